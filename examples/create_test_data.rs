@@ -0,0 +1,23 @@
+//! Writes the test fixtures in the repository root:
+//! `cargo run --example create_test_data`
+use shairport_sync_metadata_reader_rs::{Result, ShairportMetadata, encoder};
+
+fn main() -> Result<()> {
+    let test_items = [
+        ShairportMetadata::Title("Hello World".to_string()),
+        ShairportMetadata::Artist("Test Artist".to_string()),
+        ShairportMetadata::PlayBegin,
+        ShairportMetadata::PlayEnd,
+    ];
+
+    let mut xml = Vec::new();
+    let mut binary = Vec::new();
+    for metadata in &test_items {
+        xml.extend(encoder::encode_xml_metadata(metadata)?);
+        binary.extend(encoder::encode_binary_metadata(metadata)?);
+    }
+    std::fs::write("test_input.xml", xml)?;
+    std::fs::write("test_metadata.bin", binary)?;
+
+    Ok(())
+}
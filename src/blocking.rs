@@ -0,0 +1,103 @@
+use crate::error::Result;
+use crate::metadata::ShairportMetadata;
+use crate::parser::{AutoDetectParser, InputFormat, ParserConfig};
+use log::{debug, info};
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+
+// Size of the chunks handed to the parser
+const READ_CHUNK_SIZE: usize = 8192;
+
+/// Blocking reader over any `std::io::Read`: a file, a named pipe, stdin, a
+/// serial port... Each item is parsed as soon as enough bytes have been read.
+/// Errors are yielded as items and end the iteration; `None` means EOF.
+pub struct MetadataIter<R> {
+    reader: R,
+    parser: AutoDetectParser,
+    chunk: Box<[u8]>,
+    eof: bool,
+    done: bool,
+}
+
+impl MetadataIter<File> {
+    /// Open a metadata file or named pipe. Opening a pipe blocks until
+    /// shairport-sync opens it for writing.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_config(path, ParserConfig::default())
+    }
+
+    pub fn open_with_config<P: AsRef<Path>>(path: P, config: ParserConfig) -> Result<Self> {
+        info!("Reading metadata from: {}", path.as_ref().display());
+        Ok(Self::with_config(File::open(path)?, config))
+    }
+}
+
+impl<R: Read> MetadataIter<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_config(reader, ParserConfig::default())
+    }
+
+    pub fn with_config(reader: R, config: ParserConfig) -> Self {
+        Self {
+            reader,
+            parser: AutoDetectParser::with_config(config),
+            chunk: vec![0u8; READ_CHUNK_SIZE].into_boxed_slice(),
+            eof: false,
+            done: false,
+        }
+    }
+
+    /// The format in use, `None` while still detecting
+    pub fn format(&self) -> Option<InputFormat> {
+        self.parser.format()
+    }
+
+    /// Give back the underlying reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Iterator for MetadataIter<R> {
+    type Item = Result<ShairportMetadata>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        loop {
+            match self.parser.parse_next_metadata() {
+                Ok(Some(metadata)) => {
+                    debug!("Parsed metadata: {}", metadata.get_type_name());
+                    return Some(Ok(metadata));
+                }
+                Ok(None) => self.parser.clear_processed(),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+
+            if self.eof {
+                self.done = true;
+                return None;
+            }
+            match self.reader.read(&mut self.chunk) {
+                Ok(0) => {
+                    debug!("Reached EOF");
+                    // Parse what is left once the format is known
+                    self.eof = true;
+                    self.parser.finish();
+                }
+                Ok(n) => self.parser.feed_data(&self.chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+    }
+}
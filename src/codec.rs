@@ -0,0 +1,249 @@
+use crate::encoder::{metadata_item, write_binary_item, write_xml_item};
+use crate::error::{MetadataError, Result};
+use crate::metadata::{MetadataItem, ShairportMetadata};
+use crate::parser::{
+    FormatDetector, InputFormat, MetadataParser, ParserConfig, XmlMetadataParser,
+};
+use bytes::{Buf, BytesMut};
+use log::{info, warn};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// `tokio_util` codec for shairport-sync's pipe XML format, e.g.
+/// `FramedRead::new(pipe, ShairportXmlCodec::new())`. Decodes to
+/// `ShairportMetadata`, encodes metadata or raw items.
+pub struct ShairportXmlCodec {
+    parser: XmlMetadataParser,
+}
+
+impl ShairportXmlCodec {
+    pub fn new() -> Self {
+        Self::with_config(ParserConfig::default())
+    }
+
+    pub fn with_config(config: ParserConfig) -> Self {
+        Self {
+            parser: XmlMetadataParser::with_config(config),
+        }
+    }
+}
+
+impl Default for ShairportXmlCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for ShairportXmlCodec {
+    type Item = ShairportMetadata;
+    type Error = MetadataError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<ShairportMetadata>> {
+        let result = self.parser.parse_metadata_in(src);
+        src.advance(self.parser.release_processed());
+        result
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<ShairportMetadata>> {
+        let metadata = self.decode(src)?;
+        discard_at_eof(src, metadata.is_none());
+        Ok(metadata)
+    }
+}
+
+impl Encoder<MetadataItem> for ShairportXmlCodec {
+    type Error = MetadataError;
+
+    fn encode(&mut self, item: MetadataItem, dst: &mut BytesMut) -> Result<()> {
+        let mut out = Vec::new();
+        write_xml_item(&mut out, &item)?;
+        dst.extend_from_slice(&out);
+        Ok(())
+    }
+}
+
+impl Encoder<ShairportMetadata> for ShairportXmlCodec {
+    type Error = MetadataError;
+
+    fn encode(&mut self, metadata: ShairportMetadata, dst: &mut BytesMut) -> Result<()> {
+        self.encode(metadata_item(&metadata)?, dst)
+    }
+}
+
+/// `tokio_util` codec for the `<type:4><code:4><length:u32 BE><data>`
+/// binary framing.
+pub struct ShairportBinaryCodec {
+    parser: MetadataParser,
+}
+
+impl ShairportBinaryCodec {
+    pub fn new() -> Self {
+        Self::with_config(ParserConfig::default())
+    }
+
+    pub fn with_config(config: ParserConfig) -> Self {
+        Self {
+            parser: MetadataParser::with_config(config),
+        }
+    }
+}
+
+impl Default for ShairportBinaryCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for ShairportBinaryCodec {
+    type Item = ShairportMetadata;
+    type Error = MetadataError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<ShairportMetadata>> {
+        let result = self.parser.parse_metadata_in(src);
+        src.advance(self.parser.release_processed());
+        result
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<ShairportMetadata>> {
+        let metadata = self.decode(src)?;
+        discard_at_eof(src, metadata.is_none());
+        Ok(metadata)
+    }
+}
+
+impl Encoder<MetadataItem> for ShairportBinaryCodec {
+    type Error = MetadataError;
+
+    fn encode(&mut self, item: MetadataItem, dst: &mut BytesMut) -> Result<()> {
+        let mut out = Vec::with_capacity(12 + item.data.len());
+        write_binary_item(&mut out, &item)?;
+        dst.extend_from_slice(&out);
+        Ok(())
+    }
+}
+
+impl Encoder<ShairportMetadata> for ShairportBinaryCodec {
+    type Error = MetadataError;
+
+    fn encode(&mut self, metadata: ShairportMetadata, dst: &mut BytesMut) -> Result<()> {
+        self.encode(metadata_item(&metadata)?, dst)
+    }
+}
+
+enum CodecState {
+    Detecting(FormatDetector),
+    Xml(ShairportXmlCodec),
+    Binary(ShairportBinaryCodec),
+}
+
+/// Codec that picks the XML or binary codec from the first bytes of the
+/// stream, or uses the format forced in `ParserConfig::format`. Encodes as
+/// XML unless the binary format is in use.
+pub struct ShairportCodec {
+    config: ParserConfig,
+    state: CodecState,
+}
+
+impl ShairportCodec {
+    pub fn new() -> Self {
+        Self::with_config(ParserConfig::default())
+    }
+
+    pub fn with_config(config: ParserConfig) -> Self {
+        let state = match config.format {
+            InputFormat::Auto => CodecState::Detecting(FormatDetector::with_config(&config)),
+            InputFormat::Xml => CodecState::Xml(ShairportXmlCodec::with_config(config.clone())),
+            InputFormat::Binary => CodecState::Binary(ShairportBinaryCodec::with_config(config.clone())),
+        };
+        Self { config, state }
+    }
+
+    /// The format in use, `None` while still detecting
+    pub fn format(&self) -> Option<InputFormat> {
+        match self.state {
+            CodecState::Detecting(_) => None,
+            CodecState::Xml(_) => Some(InputFormat::Xml),
+            CodecState::Binary(_) => Some(InputFormat::Binary),
+        }
+    }
+
+    fn start(&mut self, format: InputFormat, src: &[u8]) {
+        info!("Detected {:?} metadata format", format);
+        self.state = match format {
+            InputFormat::Binary => CodecState::Binary(ShairportBinaryCodec {
+                parser: MetadataParser::detected(self.config.clone(), src),
+            }),
+            _ => CodecState::Xml(ShairportXmlCodec::with_config(self.config.clone())),
+        };
+    }
+}
+
+impl Default for ShairportCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for ShairportCodec {
+    type Item = ShairportMetadata;
+    type Error = MetadataError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<ShairportMetadata>> {
+        if let CodecState::Detecting(detector) = &mut self.state {
+            // Detection only looks at the buffered bytes, nothing is consumed
+            match detector.detect(src, false) {
+                Some(format) => self.start(format, src),
+                None => return Ok(None),
+            }
+        }
+
+        match &mut self.state {
+            CodecState::Detecting(_) => Ok(None),
+            CodecState::Xml(codec) => codec.decode(src),
+            CodecState::Binary(codec) => codec.decode(src),
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<ShairportMetadata>> {
+        // The whole rest of the stream is buffered, detect from all of it
+        if let CodecState::Detecting(detector) = &mut self.state {
+            let format = detector.detect(src, true).unwrap_or(InputFormat::Xml);
+            self.start(format, src);
+        }
+        let metadata = self.decode(src)?;
+        discard_at_eof(src, metadata.is_none());
+        Ok(metadata)
+    }
+}
+
+impl Encoder<MetadataItem> for ShairportCodec {
+    type Error = MetadataError;
+
+    fn encode(&mut self, item: MetadataItem, dst: &mut BytesMut) -> Result<()> {
+        match &mut self.state {
+            CodecState::Binary(codec) => codec.encode(item, dst),
+            _ => {
+                let mut out = Vec::new();
+                write_xml_item(&mut out, &item)?;
+                dst.extend_from_slice(&out);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Encoder<ShairportMetadata> for ShairportCodec {
+    type Error = MetadataError;
+
+    fn encode(&mut self, metadata: ShairportMetadata, dst: &mut BytesMut) -> Result<()> {
+        self.encode(metadata_item(&metadata)?, dst)
+    }
+}
+
+// A partial item left at end of stream can never complete, drop it rather
+// than failing the whole stream
+fn discard_at_eof(src: &mut BytesMut, exhausted: bool) {
+    if exhausted && !src.is_empty() {
+        warn!("Discarding {} bytes of incomplete item at end of stream", src.len());
+        src.clear();
+    }
+}
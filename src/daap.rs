@@ -0,0 +1,167 @@
+//! Wire types of the DAAP codes shairport-sync forwards as `core` items
+
+use crate::fourcc::FourCC;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// How a DAAP value is encoded. Integers are big-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DaapType {
+    Byte,
+    Short,
+    Int,
+    Long,
+    String,
+    /// 32-bit seconds since the Mac epoch, 1904-01-01 UTC
+    Date,
+}
+
+impl DaapType {
+    /// Size of a value in bytes, `None` for strings
+    pub fn width(self) -> Option<usize> {
+        match self {
+            Self::Byte => Some(1),
+            Self::Short => Some(2),
+            Self::Int | Self::Date => Some(4),
+            Self::Long => Some(8),
+            Self::String => None,
+        }
+    }
+}
+
+// Codes with a known type, sorted by code. Every one has its own
+// `ShairportMetadata` variant.
+pub(crate) const DAAP_CODES: &[(FourCC, DaapType)] = &[
+    (FourCC::new(b"asaa"), DaapType::String), // album artist
+    (FourCC::new(b"asai"), DaapType::Long),   // album id
+    (FourCC::new(b"asal"), DaapType::String), // album
+    (FourCC::new(b"asar"), DaapType::String), // artist
+    (FourCC::new(b"asbr"), DaapType::Short),  // bitrate
+    (FourCC::new(b"asbt"), DaapType::Short),  // beats per minute
+    (FourCC::new(b"ascm"), DaapType::String), // comment
+    (FourCC::new(b"asco"), DaapType::Byte),   // compilation flag
+    (FourCC::new(b"ascp"), DaapType::String), // composer
+    (FourCC::new(b"asda"), DaapType::Date),   // date added
+    (FourCC::new(b"asdc"), DaapType::Short),  // disc count
+    (FourCC::new(b"asdk"), DaapType::Byte),   // data kind
+    (FourCC::new(b"asdm"), DaapType::Date),   // date modified
+    (FourCC::new(b"asdn"), DaapType::Short),  // disc number
+    (FourCC::new(b"asdt"), DaapType::String), // description
+    (FourCC::new(b"asfm"), DaapType::String), // format
+    (FourCC::new(b"asgn"), DaapType::String), // genre
+    (FourCC::new(b"askd"), DaapType::Date),   // last skip date
+    (FourCC::new(b"asky"), DaapType::String), // keywords
+    (FourCC::new(b"assa"), DaapType::String), // sort artist
+    (FourCC::new(b"assc"), DaapType::String), // sort composer
+    (FourCC::new(b"assn"), DaapType::String), // sort name
+    (FourCC::new(b"assr"), DaapType::Int),    // sample rate
+    (FourCC::new(b"asst"), DaapType::Int),    // start time in milliseconds
+    (FourCC::new(b"assu"), DaapType::String), // sort album
+    (FourCC::new(b"assz"), DaapType::Int),    // size
+    (FourCC::new(b"astc"), DaapType::Short),  // track count
+    (FourCC::new(b"astm"), DaapType::Int),    // track time in milliseconds
+    (FourCC::new(b"astn"), DaapType::Short),  // track number
+    (FourCC::new(b"asul"), DaapType::String), // data url
+    (FourCC::new(b"asur"), DaapType::Byte),   // user rating
+    (FourCC::new(b"asyr"), DaapType::Short),  // year
+    (FourCC::new(b"caps"), DaapType::Byte),   // play status
+    (FourCC::new(b"miid"), DaapType::Int),    // item id
+    (FourCC::new(b"mikd"), DaapType::Byte),   // media kind
+    (FourCC::new(b"minm"), DaapType::String), // item name
+    (FourCC::new(b"mper"), DaapType::Long),   // persistent id
+];
+
+/// Type of a DAAP code, `None` for codes not in the table
+pub fn daap_type(code: FourCC) -> Option<DaapType> {
+    DAAP_CODES
+        .binary_search_by(|(known, _)| known.cmp(&code))
+        .ok()
+        .map(|i| DAAP_CODES[i].1)
+}
+
+/// A value decoded according to the type of its code
+#[derive(Debug, Clone, PartialEq)]
+pub enum DaapValue {
+    Byte(u8),
+    Short(u16),
+    Int(u32),
+    Long(u64),
+    String(String),
+    Date(Timestamp),
+}
+
+impl DaapValue {
+    /// Decode a payload, `None` if it has the wrong size for the type or a
+    /// string isn't UTF-8
+    pub fn decode(daap_type: DaapType, data: &[u8]) -> Option<Self> {
+        Some(match daap_type {
+            DaapType::Byte => Self::Byte(u8::from_be_bytes(data.try_into().ok()?)),
+            DaapType::Short => Self::Short(u16::from_be_bytes(data.try_into().ok()?)),
+            DaapType::Int => Self::Int(u32_value(data)?),
+            DaapType::Long => Self::Long(u64::from_be_bytes(data.try_into().ok()?)),
+            DaapType::String => Self::String(String::from_utf8(data.to_vec()).ok()?),
+            DaapType::Date => Self::Date(date_value(data)?),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Byte(n) => n.to_be_bytes().to_vec(),
+            Self::Short(n) => n.to_be_bytes().to_vec(),
+            Self::Int(n) => n.to_be_bytes().to_vec(),
+            Self::Long(n) => n.to_be_bytes().to_vec(),
+            Self::String(s) => s.as_bytes().to_vec(),
+            Self::Date(date) => date_bytes(date).to_vec(),
+        }
+    }
+}
+
+/// A DAAP date. Without the `std` feature it is the time since the Unix epoch.
+#[cfg(feature = "std")]
+pub type Timestamp = std::time::SystemTime;
+#[cfg(not(feature = "std"))]
+pub type Timestamp = Duration;
+
+// Seconds from the Mac epoch to the Unix epoch
+const MAC_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+fn u32_value(data: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(data.try_into().ok()?))
+}
+
+// Durations past u32::MAX milliseconds (about 49 days) are clamped
+pub(crate) fn millis(duration: &Duration) -> u32 {
+    u32::try_from(duration.as_millis()).unwrap_or(u32::MAX)
+}
+
+fn date_value(data: &[u8]) -> Option<Timestamp> {
+    let unix_secs = i64::from(u32_value(data)?) - MAC_EPOCH_OFFSET;
+    #[cfg(feature = "std")]
+    {
+        let offset = Duration::from_secs(unix_secs.unsigned_abs());
+        if unix_secs >= 0 {
+            std::time::UNIX_EPOCH.checked_add(offset)
+        } else {
+            std::time::UNIX_EPOCH.checked_sub(offset)
+        }
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        Some(Duration::from_secs(unix_secs.max(0) as u64))
+    }
+}
+
+// Dates outside what 32-bit Mac time can hold are clamped
+fn date_bytes(date: &Timestamp) -> [u8; 4] {
+    #[cfg(feature = "std")]
+    let unix_secs = match date.duration_since(std::time::UNIX_EPOCH) {
+        Ok(since) => i64::try_from(since.as_secs()).unwrap_or(i64::MAX),
+        Err(e) => -i64::try_from(e.duration().as_secs()).unwrap_or(i64::MAX),
+    };
+    #[cfg(not(feature = "std"))]
+    let unix_secs = i64::try_from(date.as_secs()).unwrap_or(i64::MAX);
+
+    let mac_secs = unix_secs.saturating_add(MAC_EPOCH_OFFSET).clamp(0, u32::MAX.into());
+    (mac_secs as u32).to_be_bytes()
+}
@@ -0,0 +1,80 @@
+use crate::error::{MetadataError, Result};
+use alloc::format;
+use alloc::vec::Vec;
+use crate::metadata::{MetadataItem, ShairportMetadata};
+use base64::{engine::general_purpose, Engine as _};
+
+/// Encode an item in shairport-sync's pipe XML format, byte for byte as
+/// shairport-sync writes it:
+///
+/// ```text
+/// <item><type>636f7265</type><code>6d696e6d</code><length>11</length>
+/// <data encoding="base64">
+/// SGVsbG8gV29ybGQ=</data></item>
+/// ```
+///
+/// Items without data are written on a single line.
+pub fn encode_xml_item(item: &MetadataItem) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    write_xml_item(&mut out, item)?;
+    Ok(out)
+}
+
+/// Encode metadata in pipe XML format. Parser events such as
+/// `ShairportMetadata::Diagnostic` have no item and can't be encoded.
+pub fn encode_xml_metadata(metadata: &ShairportMetadata) -> Result<Vec<u8>> {
+    encode_xml_item(&metadata_item(metadata)?)
+}
+
+/// Append an item in pipe XML format to `out`
+pub fn write_xml_item(out: &mut Vec<u8>, item: &MetadataItem) -> Result<()> {
+    out.extend_from_slice(
+        format!(
+            "<item><type>{:08x}</type><code>{:08x}</code><length>{}</length>",
+            item.item_type.to_u32(),
+            item.code.to_u32(),
+            item.data.len()
+        )
+        .as_bytes(),
+    );
+    if !item.data.is_empty() {
+        out.extend_from_slice(b"\n<data encoding=\"base64\">\n");
+        out.extend_from_slice(general_purpose::STANDARD.encode(&item.data).as_bytes());
+        out.extend_from_slice(b"</data>");
+    }
+    out.extend_from_slice(b"</item>\n");
+    Ok(())
+}
+
+/// Encode an item in the binary framing read by `MetadataParser`:
+/// `<type:4><code:4><length:u32 BE><data>`
+pub fn encode_binary_item(item: &MetadataItem) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(12 + item.data.len());
+    write_binary_item(&mut out, item)?;
+    Ok(out)
+}
+
+/// Encode metadata in the binary framing. Parser events such as
+/// `ShairportMetadata::Diagnostic` have no item and can't be encoded.
+pub fn encode_binary_metadata(metadata: &ShairportMetadata) -> Result<Vec<u8>> {
+    encode_binary_item(&metadata_item(metadata)?)
+}
+
+/// Append an item in the binary framing to `out`
+pub fn write_binary_item(out: &mut Vec<u8>, item: &MetadataItem) -> Result<()> {
+    let length = u32::try_from(item.data.len()).map_err(|_| {
+        MetadataError::Encode(format!("{} bytes don't fit a u32 length", item.data.len()))
+    })?;
+
+    out.extend_from_slice(item.item_type.as_bytes());
+    out.extend_from_slice(item.code.as_bytes());
+    out.extend_from_slice(&length.to_be_bytes());
+    out.extend_from_slice(&item.data);
+    Ok(())
+}
+
+pub(crate) fn metadata_item(metadata: &ShairportMetadata) -> Result<MetadataItem> {
+    metadata.to_item().ok_or_else(|| {
+        MetadataError::Encode(format!("{} has no metadata item", metadata.get_type_name()))
+    })
+}
@@ -1,7 +1,10 @@
+use crate::fourcc::FourCC;
+use alloc::string::{FromUtf8Error, String};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum MetadataError {
+    #[cfg(feature = "std")]
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     
@@ -12,7 +15,85 @@ pub enum MetadataError {
     InvalidFormat,
     
     #[error("UTF-8 conversion error: {0}")]
-    Utf8(#[from] std::string::FromUtf8Error),
+    Utf8(#[from] FromUtf8Error),
+
+    #[error("Length mismatch in {item_type}:{code} at offset {offset}: declared {expected} bytes, got {actual}")]
+    LengthMismatch {
+        item_type: FourCC,
+        code: FourCC,
+        offset: u64,
+        expected: usize,
+        actual: usize,
+    },
+
+    #[error("Invalid base64 in {item_type}:{code} at offset {offset}: {message}")]
+    InvalidBase64 {
+        item_type: FourCC,
+        code: FourCC,
+        offset: u64,
+        message: String,
+    },
+
+    #[error("Invalid <{field}> value '{value}' in {item_type}:{code} at offset {offset}")]
+    InvalidField {
+        field: &'static str,
+        value: String,
+        item_type: FourCC,
+        code: FourCC,
+        offset: u64,
+    },
+
+    #[error("Item {item_type}:{code} at offset {offset} has no <{field}>")]
+    MissingField {
+        field: &'static str,
+        item_type: FourCC,
+        code: FourCC,
+        offset: u64,
+    },
+
+    #[error("Unexpected tag <{tag}> in {item_type}:{code} at offset {offset}")]
+    UnexpectedTag {
+        tag: String,
+        item_type: FourCC,
+        code: FourCC,
+        offset: u64,
+    },
+
+    #[error("Item {item_type}:{code} at offset {offset} is too large: {length} bytes, limit is {limit}")]
+    ItemTooLarge {
+        item_type: FourCC,
+        code: FourCC,
+        offset: u64,
+        length: usize,
+        limit: usize,
+    },
+
+    #[error("Buffer limit of {limit} bytes exceeded while reading {item_type}:{code} at offset {offset}")]
+    BufferOverflow {
+        item_type: FourCC,
+        code: FourCC,
+        offset: u64,
+        limit: usize,
+    },
+
+    #[error("Encode error: {0}")]
+    Encode(String),
+}
+
+impl MetadataError {
+    /// Whether the error only affects a single item, so parsing can carry on
+    pub fn is_item_error(&self) -> bool {
+        matches!(
+            self,
+            Self::LengthMismatch { .. }
+                | Self::InvalidBase64 { .. }
+                | Self::InvalidField { .. }
+                | Self::MissingField { .. }
+                | Self::UnexpectedTag { .. }
+                | Self::ItemTooLarge { .. }
+                | Self::BufferOverflow { .. }
+        )
+    }
 }
 
-pub type Result<T> = std::result::Result<T, MetadataError>;
\ No newline at end of file
+pub type Result<T> = core::result::Result<T, MetadataError>;
\ No newline at end of file
@@ -0,0 +1,255 @@
+use crate::error::Result;
+use log::{debug, info};
+use std::io::{self, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncRead, AsyncSeekExt, ReadBuf};
+#[cfg(unix)]
+use tokio::net::unix::pipe;
+
+/// What a reader does when the metadata path doesn't exist yet
+#[derive(Debug, Clone, Default)]
+pub enum MissingPath {
+    /// Fail with an I/O error
+    #[default]
+    Fail,
+    /// Create a named pipe for shairport-sync to write to
+    Create(FifoOptions),
+    /// Wait for another process, usually shairport-sync, to create it
+    Wait,
+}
+
+/// Permissions of a named pipe created by the reader
+#[derive(Debug, Clone)]
+pub struct FifoOptions {
+    /// Permission bits, applied regardless of the umask
+    pub mode: u32,
+    /// User to give the pipe to, `None` keeps the current user
+    pub owner: Option<u32>,
+    /// Group to give the pipe to, `None` keeps the current group
+    pub group: Option<u32>,
+}
+
+impl Default for FifoOptions {
+    // Writable by shairport-sync running as another user
+    fn default() -> Self {
+        Self {
+            mode: 0o666,
+            owner: None,
+            group: None,
+        }
+    }
+}
+
+/// An opened metadata path. Named pipes are opened with `O_NONBLOCK` and read
+/// on readiness, so neither opening nor waiting for a writer ties up a thread
+/// and dropping a pending read cancels it.
+pub(crate) enum Source {
+    File(File),
+    #[cfg(unix)]
+    Pipe(pipe::Receiver),
+}
+
+impl Source {
+    pub(crate) async fn open(path: &Path) -> Result<Self> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            if tokio::fs::metadata(path).await?.file_type().is_fifo() {
+                debug!("Opening {} as a named pipe", path.display());
+                return Ok(Self::Pipe(pipe::OpenOptions::new().open_receiver(path)?));
+            }
+        }
+        Ok(Self::File(OpenOptions::new().read(true).open(path).await?))
+    }
+
+    pub(crate) fn is_fifo(&self) -> bool {
+        match self {
+            Self::File(_) => false,
+            #[cfg(unix)]
+            Self::Pipe(_) => true,
+        }
+    }
+
+    /// Device and inode of an open file, to notice its path being replaced
+    pub(crate) async fn file_id(&self) -> Result<Option<FileId>> {
+        match self {
+            Self::File(file) => Ok(metadata_id(&file.metadata().await?)),
+            #[cfg(unix)]
+            Self::Pipe(_) => Ok(None),
+        }
+    }
+
+    /// Current size of an open file, to notice it being truncated
+    pub(crate) async fn file_len(&self) -> Result<u64> {
+        match self {
+            Self::File(file) => Ok(file.metadata().await?.len()),
+            #[cfg(unix)]
+            Self::Pipe(_) => Ok(0),
+        }
+    }
+
+    pub(crate) async fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        match self {
+            Self::File(file) => Ok(file.seek(pos).await?),
+            #[cfg(unix)]
+            Self::Pipe(_) => Ok(0),
+        }
+    }
+
+    /// Wait until a pipe has data or a writer hung up, right away for a file
+    pub(crate) async fn readable(&self) -> Result<()> {
+        match self {
+            Self::File(_) => Ok(()),
+            #[cfg(unix)]
+            Self::Pipe(receiver) => Ok(receiver.readable().await?),
+        }
+    }
+}
+
+/// Device and inode number
+pub(crate) type FileId = (u64, u64);
+
+/// Identity of the file currently at `path`, `None` if there is none
+pub(crate) async fn path_file_id(path: &Path) -> Result<Option<FileId>> {
+    match tokio::fs::metadata(path).await {
+        Ok(metadata) => Ok(metadata_id(&metadata)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(unix)]
+fn metadata_id(metadata: &std::fs::Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn metadata_id(_metadata: &std::fs::Metadata) -> Option<FileId> {
+    None
+}
+
+impl AsyncRead for Source {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::File(file) => Pin::new(file).poll_read(cx, buf),
+            #[cfg(unix)]
+            Self::Pipe(receiver) => Pin::new(receiver).poll_read(cx, buf),
+        }
+    }
+}
+
+// Make sure `path` exists before opening it, according to `missing`
+pub(crate) async fn prepare_path(path: &Path, missing: &MissingPath) -> Result<()> {
+    if matches!(missing, MissingPath::Fail) || tokio::fs::try_exists(path).await? {
+        return Ok(());
+    }
+
+    match missing {
+        MissingPath::Fail => Ok(()),
+        MissingPath::Create(options) => create_fifo(path, options),
+        MissingPath::Wait => {
+            info!("Waiting for {} to be created", path.display());
+            wait_for(path).await?;
+            info!("{} was created", path.display());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(unix)]
+fn create_fifo(path: &Path, options: &FifoOptions) -> Result<()> {
+    use nix::errno::Errno;
+    use nix::sys::stat::Mode;
+    use std::os::unix::fs::PermissionsExt;
+
+    info!("Creating named pipe {} with mode {:o}", path.display(), options.mode);
+    match nix::unistd::mkfifo(path, Mode::from_bits_truncate(options.mode as _)) {
+        // Created by someone else in the meantime
+        Err(Errno::EEXIST) => return Ok(()),
+        result => result.map_err(io::Error::from)?,
+    }
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(options.mode))?;
+    if options.owner.is_some() || options.group.is_some() {
+        std::os::unix::fs::chown(path, options.owner, options.group)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_fifo(path: &Path, _options: &FifoOptions) -> Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("can't create named pipe {} on this platform", path.display()),
+    )
+    .into())
+}
+
+async fn wait_for(path: &Path) -> Result<()> {
+    let mut watcher = PathWatcher::new(path)?;
+    // It may have been created before the watch was in place
+    while !tokio::fs::try_exists(path).await? {
+        watcher.changed().await?;
+    }
+    Ok(())
+}
+
+/// Wakes up when a file is written to, truncated, created or moved into
+/// place. Watches the parent directory with inotify, so it keeps working
+/// across rotations; other platforms poll.
+pub(crate) struct PathWatcher {
+    #[cfg(target_os = "linux")]
+    events: inotify::EventStream<[u8; 1024]>,
+    #[cfg(target_os = "linux")]
+    name: std::ffi::OsString,
+}
+
+impl PathWatcher {
+    #[cfg(target_os = "linux")]
+    pub(crate) fn new(path: &Path) -> Result<Self> {
+        use inotify::{Inotify, WatchMask};
+
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} has no file name", path.display())))?;
+        let inotify = Inotify::init()?;
+        inotify
+            .watches()
+            .add(dir, WatchMask::MODIFY | WatchMask::CREATE | WatchMask::MOVED_TO)?;
+        Ok(Self {
+            events: inotify.into_event_stream([0u8; 1024])?,
+            name: name.to_owned(),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn new(_path: &Path) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    /// Wait for the next change to the watched path
+    #[cfg(target_os = "linux")]
+    pub(crate) async fn changed(&mut self) -> Result<()> {
+        use futures::StreamExt;
+
+        while let Some(event) = self.events.next().await {
+            if event?.name.as_deref() == Some(self.name.as_os_str()) {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) async fn changed(&mut self) -> Result<()> {
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+//! Four-character codes naming metadata types and codes
+
+use crate::error::MetadataError;
+use alloc::format;
+use core::fmt;
+use core::str::FromStr;
+
+/// A four-character code such as `core` or `minm`, kept as its raw bytes.
+/// Shown as text when all four bytes are printable ASCII and as `0x%08x`
+/// otherwise; `FromStr` accepts both forms.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FourCC(pub [u8; 4]);
+
+impl FourCC {
+    pub const CORE: Self = Self::new(b"core");
+    pub const SSNC: Self = Self::new(b"ssnc");
+    pub const PICT: Self = Self::new(b"pict");
+
+    pub const fn new(bytes: &[u8; 4]) -> Self {
+        Self(*bytes)
+    }
+
+    pub const fn from_u32(value: u32) -> Self {
+        Self(value.to_be_bytes())
+    }
+
+    pub const fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    pub const fn as_bytes(&self) -> &[u8; 4] {
+        &self.0
+    }
+
+    /// Whether it can be shown as four characters
+    pub fn is_printable(&self) -> bool {
+        self.0.iter().all(|&b| b.is_ascii_graphic() || b == b' ')
+    }
+}
+
+impl From<[u8; 4]> for FourCC {
+    fn from(bytes: [u8; 4]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<u32> for FourCC {
+    fn from(value: u32) -> Self {
+        Self::from_u32(value)
+    }
+}
+
+impl From<FourCC> for u32 {
+    fn from(fourcc: FourCC) -> Self {
+        fourcc.to_u32()
+    }
+}
+
+impl FromStr for FourCC {
+    type Err = MetadataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(bytes) = s.as_bytes().try_into() {
+            return Ok(Self(bytes));
+        }
+        s.strip_prefix("0x")
+            .filter(|hex| hex.len() == 8)
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .map(Self::from_u32)
+            .ok_or_else(|| MetadataError::Parse(format!("'{}' is not a four-character code", s)))
+    }
+}
+
+impl fmt::Display for FourCC {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_printable() {
+            self.0.iter().try_for_each(|&b| write!(f, "{}", b as char))
+        } else {
+            write!(f, "0x{:08x}", self.to_u32())
+        }
+    }
+}
+
+impl fmt::Debug for FourCC {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_printable() {
+            write!(f, "FourCC(\"{}\")", self)
+        } else {
+            write!(f, "FourCC({})", self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn parses_four_characters() {
+        assert_eq!("minm".parse::<FourCC>().unwrap(), FourCC::new(b"minm"));
+        assert_eq!("PIC ".parse::<FourCC>().unwrap(), FourCC::new(b"PIC "));
+        // Exactly four bytes are text even when they look like hex
+        assert_eq!("0x12".parse::<FourCC>().unwrap(), FourCC::new(b"0x12"));
+    }
+
+    #[test]
+    fn parses_hex() {
+        assert_eq!("0x636f7265".parse::<FourCC>().unwrap(), FourCC::CORE);
+        assert_eq!("0x00ff107f".parse::<FourCC>().unwrap(), FourCC([0x00, 0xff, 0x10, 0x7f]));
+        assert_eq!("0xDEADBEEF".parse::<FourCC>().unwrap(), FourCC::from_u32(0xdead_beef));
+    }
+
+    #[test]
+    fn rejects_other_strings() {
+        for s in ["", "abc", "abcde", "0x1234567", "0x123456789", "0xzzzzzzzz", "12345678"] {
+            assert!(s.parse::<FourCC>().is_err(), "{:?} parsed", s);
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let codes = [
+            FourCC::CORE,
+            FourCC::new(b"PIC "),
+            FourCC([0, 0, 0, 0]),
+            FourCC([0x00, 0xff, 0x10, 0x7f]),
+            FourCC(*b"a\ncd"),
+        ];
+        for code in codes {
+            assert_eq!(code.to_string().parse::<FourCC>().unwrap(), code, "{:?}", code);
+        }
+        assert_eq!(FourCC::CORE.to_string(), "core");
+        assert_eq!(FourCC([0x00, 0xff, 0x10, 0x7f]).to_string(), "0x00ff107f");
+    }
+}
@@ -1,33 +1,108 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod blocking;
+#[cfg(feature = "tokio")]
+pub mod codec;
+pub mod daap;
+pub mod encoder;
 pub mod error;
+#[cfg(feature = "tokio")]
+pub mod fifo;
+pub mod fourcc;
 pub mod metadata;
 pub mod parser;
+#[cfg(feature = "tokio")]
 pub mod reader;
+#[cfg(feature = "std")]
+pub mod reassembly;
+pub mod ssnc;
 
+#[cfg(feature = "std")]
+pub use blocking::MetadataIter;
+#[cfg(feature = "tokio")]
+pub use codec::{ShairportBinaryCodec, ShairportCodec, ShairportXmlCodec};
+pub use daap::{DaapType, DaapValue, Timestamp, daap_type};
+pub use encoder::{encode_binary_item, encode_binary_metadata, encode_xml_item, encode_xml_metadata};
 pub use error::{MetadataError, Result};
+#[cfg(feature = "tokio")]
+pub use fifo::{FifoOptions, MissingPath};
+pub use fourcc::FourCC;
 pub use metadata::{MetadataItem, ShairportMetadata};
-pub use parser::MetadataParser;
-pub use reader::MetadataReader;
+pub use parser::{
+    AutoDetectParser, InputFormat, MetadataParser, ParseMode, ParserConfig, parse_binary_bytes,
+    parse_bytes, parse_udp_packet, parse_xml_bytes,
+};
+#[cfg(feature = "tokio")]
+pub use reader::{MetadataReader, MetadataStream, ReadEnd, ReadOutcome, UdpConfig};
+#[cfg(feature = "std")]
+pub use reassembly::ChunkReassembler;
+pub use ssnc::{FrameTime, Progress, Volume};
+
+#[cfg(feature = "tokio")]
+use std::net::SocketAddr;
+#[cfg(feature = "tokio")]
+use std::time::Duration;
+#[cfg(feature = "tokio")]
+use tokio::io::AsyncRead;
+#[cfg(feature = "tokio")]
+use tokio::net::{ToSocketAddrs, lookup_host};
+#[cfg(feature = "tokio")]
+use tokio_util::codec::FramedRead;
 
+#[cfg(feature = "tokio")]
 pub struct ShairportMetadataReader {
     reader: MetadataReader,
 }
 
+#[cfg(feature = "tokio")]
 impl ShairportMetadataReader {
+    pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
     pub fn new<P: Into<String>>(metadata_path: P) -> Self {
         Self {
             reader: MetadataReader::new(metadata_path.into()),
         }
     }
 
+    pub fn with_config<P: Into<String>>(metadata_path: P, config: ParserConfig) -> Self {
+        Self {
+            reader: MetadataReader::with_config(metadata_path.into(), config),
+        }
+    }
+
+    /// Create the metadata pipe, or wait for it, instead of failing when it
+    /// doesn't exist yet
+    pub fn with_missing_path(mut self, missing_path: MissingPath) -> Self {
+        self.reader.missing_path = missing_path;
+        self
+    }
+
+    /// When following a regular file, skip what it already holds and only
+    /// report items appended from now on
+    pub fn with_start_at_end(mut self, start_at_end: bool) -> Self {
+        self.reader.start_at_end = start_at_end;
+        self
+    }
+
+    /// Parse metadata from any async byte source, e.g. the stdout of
+    /// `ssh pi cat /tmp/shairport-sync-metadata`. The path is not used.
+    pub fn read_from<R: AsyncRead>(&self, reader: R) -> FramedRead<R, ShairportCodec> {
+        self.reader.read_from(reader)
+    }
+
+    /// Read what is available within `DEFAULT_READ_TIMEOUT`. Use
+    /// `read_metadata_with_timeout` to know whether the read was cut short.
     pub async fn read_metadata_once(&mut self) -> Result<Vec<ShairportMetadata>> {
-        // Add timeout for reading from potentially blocking sources
-        tokio::time::timeout(
-            tokio::time::Duration::from_secs(5),
-            self.reader.read_from_file()
-        ).await.unwrap_or_else(|_| {
-            log::warn!("Timeout reading metadata, returning what we have");
-            Ok(Vec::new())
-        })
+        Ok(self.read_metadata_with_timeout(Self::DEFAULT_READ_TIMEOUT).await?.items)
+    }
+
+    /// Read until EOF or `timeout`, keeping the items parsed before the
+    /// deadline when the pipe stays open
+    pub async fn read_metadata_with_timeout(&mut self, timeout: Duration) -> Result<ReadOutcome> {
+        self.reader.read_from_file_with_timeout(timeout).await
     }
 
     pub async fn read_from_pipe(&mut self) -> Result<Vec<ShairportMetadata>> {
@@ -38,13 +113,36 @@ impl ShairportMetadataReader {
         self.reader.read_from_stdin().await
     }
 
+    /// Stream metadata from stdin. The stream ends at EOF; errors are
+    /// yielded as items.
+    pub fn stream_stdin(&self) -> MetadataStream {
+        self.reader.stdin_stream()
+    }
+
+    /// Stream metadata from the metadata path, waiting for more at EOF.
+    /// Dropping the stream stops reading.
+    pub fn stream_file(&self) -> MetadataStream {
+        self.reader.follow_file()
+    }
+
+    /// Stream metadata datagrams received on `addr`. A multicast address
+    /// joins that group. Dropping the stream closes the socket.
+    pub async fn stream_udp<A: ToSocketAddrs>(&self, addr: A) -> Result<MetadataStream> {
+        let addr = resolve(addr).await?;
+        self.stream_udp_with(UdpConfig::new(addr))
+    }
+
+    pub fn stream_udp_with(&self, config: UdpConfig) -> Result<MetadataStream> {
+        Ok(self.reader.udp_stream(config.bind()?))
+    }
+
     pub async fn start_continuous_monitoring(&mut self) -> Result<tokio::sync::mpsc::UnboundedReceiver<ShairportMetadata>> {
         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
         
-        let mut reader_clone = MetadataReader::new(self.reader.metadata_path.clone());
+        let mut reader_clone = self.reader.clone();
         tokio::spawn(async move {
             if let Err(e) = reader_clone.start_continuous_reading(tx).await {
-                eprintln!("Error in continuous reading: {}", e);
+                log::error!("Error in continuous reading: {}", e);
             }
         });
         
@@ -54,13 +152,43 @@ impl ShairportMetadataReader {
     pub async fn start_continuous_monitoring_from_file(&mut self) -> Result<tokio::sync::mpsc::UnboundedReceiver<ShairportMetadata>> {
         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
         
-        let mut reader_clone = MetadataReader::new(self.reader.metadata_path.clone());
+        let mut reader_clone = self.reader.clone();
         tokio::spawn(async move {
             if let Err(e) = reader_clone.start_continuous_reading_from_file(tx).await {
-                eprintln!("Error in continuous reading: {}", e);
+                log::error!("Error in continuous reading: {}", e);
             }
         });
         
         Ok(rx)
     }
-}
\ No newline at end of file
+
+    /// Listen for metadata datagrams sent by shairport-sync's metadata socket.
+    /// A multicast address joins that group.
+    pub async fn start_udp_monitoring<A: ToSocketAddrs>(&mut self, addr: A) -> Result<tokio::sync::mpsc::UnboundedReceiver<ShairportMetadata>> {
+        let addr = resolve(addr).await?;
+        self.start_udp_monitoring_with(UdpConfig::new(addr)).await
+    }
+
+    pub async fn start_udp_monitoring_with(&mut self, config: UdpConfig) -> Result<tokio::sync::mpsc::UnboundedReceiver<ShairportMetadata>> {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+        // Bind here so address errors reach the caller
+        let socket = config.bind()?;
+        let mut reader_clone = self.reader.clone();
+        tokio::spawn(async move {
+            if let Err(e) = reader_clone.start_udp_reading(socket, tx).await {
+                log::error!("Error in UDP reading: {}", e);
+            }
+        });
+
+        Ok(rx)
+    }
+}
+
+#[cfg(feature = "tokio")]
+async fn resolve<A: ToSocketAddrs>(addr: A) -> Result<SocketAddr> {
+    lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| MetadataError::Parse("UDP address did not resolve".to_string()))
+}
@@ -1,8 +1,14 @@
 use clap::{Arg, ArgAction, Command};
 use env_logger::Env;
+use futures::StreamExt;
 use log::{error, info};
-use shairport_sync_metadata_reader_rs::{Result, ShairportMetadata, ShairportMetadataReader};
+use shairport_sync_metadata_reader_rs::{
+    FifoOptions, InputFormat, MetadataError, MetadataStream, MissingPath, ParseMode, ParserConfig, Result, ShairportMetadata,
+    ShairportMetadataReader, UdpConfig,
+};
+use std::net::Ipv4Addr;
 use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::signal;
 
 #[tokio::main]
@@ -28,6 +34,13 @@ async fn main() -> Result<()> {
                 .help("Continuously monitor for new metadata")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("from-end")
+                .long("from-end")
+                .help("When following a regular file, only show items appended from now on")
+                .action(ArgAction::SetTrue)
+                .requires("continuous"),
+        )
         .arg(
             Arg::new("once")
                 .short('o')
@@ -35,6 +48,14 @@ async fn main() -> Result<()> {
                 .help("Read metadata once and exit")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .value_name("SECONDS")
+                .help("With --once, stop reading an open pipe after this long [default: 5]")
+                .value_parser(clap::value_parser!(f64))
+                .requires("once"),
+        )
         .arg(
             Arg::new("stdin")
                 .short('s')
@@ -42,36 +63,124 @@ async fn main() -> Result<()> {
                 .help("Read from stdin (for piping from shairport-sync)")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("udp")
+                .short('u')
+                .long("udp")
+                .value_name("ADDR:PORT")
+                .help("Listen for metadata datagrams from shairport-sync's metadata socket (a multicast address joins the group)")
+                .conflicts_with_all(["stdin", "once"]),
+        )
+        .arg(
+            Arg::new("multicast-interface")
+                .long("multicast-interface")
+                .value_name("IFACE")
+                .help("Interface for the multicast group: local IPv4 address or IPv6 interface index")
+                .requires("udp"),
+        )
+        .arg(
+            Arg::new("create-fifo")
+                .long("create-fifo")
+                .help("Create the named pipe if it doesn't exist")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["stdin", "udp"]),
+        )
+        .arg(
+            Arg::new("fifo-mode")
+                .long("fifo-mode")
+                .value_name("MODE")
+                .help("Octal permissions of a created pipe [default: 666]")
+                .requires("create-fifo"),
+        )
+        .arg(
+            Arg::new("fifo-owner")
+                .long("fifo-owner")
+                .value_name("UID[:GID]")
+                .help("Owner of a created pipe")
+                .requires("create-fifo"),
+        )
+        .arg(
+            Arg::new("wait-for-path")
+                .long("wait-for-path")
+                .help("Wait for the path to be created instead of failing")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["stdin", "udp", "create-fifo"]),
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .help("Stop on the first malformed item instead of skipping it")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("format")
+                .short('f')
+                .long("format")
+                .value_name("FORMAT")
+                .help("Input format: auto, xml or binary")
+                .value_parser(clap::value_parser!(InputFormat))
+                .default_value("auto"),
+        )
+        .arg(
+            Arg::new("max-item-size")
+                .long("max-item-size")
+                .value_name("BYTES")
+                .help("Skip items with a larger payload than this")
+                .value_parser(clap::value_parser!(usize)),
+        )
         .get_matches();
 
     let metadata_path = matches.get_one::<String>("path").unwrap().to_string();
     let continuous = matches.get_flag("continuous");
     let once = matches.get_flag("once");
     let stdin_mode = matches.get_flag("stdin");
+    let config = ParserConfig {
+        mode: if matches.get_flag("strict") {
+            ParseMode::Strict
+        } else {
+            ParseMode::Lenient
+        },
+        format: *matches.get_one::<InputFormat>("format").unwrap(),
+        max_item_size: matches
+            .get_one::<usize>("max-item-size")
+            .copied()
+            .unwrap_or(ParserConfig::DEFAULT_MAX_ITEM_SIZE),
+        ..ParserConfig::default()
+    };
+
+    let missing_path = match missing_path(&matches) {
+        Ok(missing_path) => missing_path,
+        Err(e) => {
+            error!("{}", e);
+            process::exit(1);
+        }
+    };
+    let fallback_to_stdin = matches!(missing_path, MissingPath::Fail);
 
-    let mut reader = ShairportMetadataReader::new(&metadata_path);
+    let mut reader = ShairportMetadataReader::with_config(&metadata_path, config)
+        .with_missing_path(missing_path)
+        .with_start_at_end(matches.get_flag("from-end"));
 
-    if stdin_mode {
+    if let Some(udp_addr) = matches.get_one::<String>("udp") {
+        info!("Listening for metadata on UDP {}", udp_addr);
+        let udp_config = match udp_config(udp_addr, matches.get_one::<String>("multicast-interface")).await {
+            Ok(udp_config) => udp_config,
+            Err(e) => {
+                error!("Invalid UDP address {}: {}", udp_addr, e);
+                process::exit(1);
+            }
+        };
+        match reader.stream_udp_with(udp_config) {
+            Ok(stream) => print_stream(stream).await,
+            Err(e) => {
+                error!("Failed to listen on UDP {}: {}", udp_addr, e);
+                process::exit(1);
+            }
+        }
+    } else if stdin_mode {
         info!("Reading metadata from stdin");
         if continuous {
-            match reader.start_continuous_monitoring().await {
-                Ok(mut rx) => {
-                    tokio::select! {
-                        _ = signal::ctrl_c() => {
-                            info!("Received Ctrl+C, shutting down...");
-                        }
-                        _ = async {
-                            while let Some(metadata) = rx.recv().await {
-                                print_metadata(&metadata);
-                            }
-                        } => {}
-                    }
-                }
-                Err(e) => {
-                    error!("Failed to start continuous monitoring from stdin: {}", e);
-                    process::exit(1);
-                }
-            }
+            print_stream(reader.stream_stdin()).await;
         } else {
             match reader.read_from_stdin().await {
                 Ok(metadata_list) => {
@@ -87,9 +196,10 @@ async fn main() -> Result<()> {
         }
     } else if once {
         info!("Reading metadata once from: {}", metadata_path);
-        // Default to stdin like C version, unless specific path given and it exists
-        let use_stdin = metadata_path == "/tmp/shairport-sync-metadata"
-            || !std::path::Path::new(&metadata_path).exists();
+        // Default to stdin like C version, unless specific path given and it
+        // exists or is going to be created
+        let use_stdin = fallback_to_stdin
+            && (metadata_path == "/tmp/shairport-sync-metadata" || !std::path::Path::new(&metadata_path).exists());
 
         if use_stdin {
             info!("Using stdin for input (like C version)");
@@ -105,10 +215,20 @@ async fn main() -> Result<()> {
                 }
             }
         } else {
-            match reader.read_metadata_once().await {
-                Ok(metadata_list) => {
-                    for metadata in metadata_list {
-                        print_metadata(&metadata);
+            let timeout = match matches.get_one::<f64>("timeout") {
+                Some(secs) => Duration::try_from_secs_f64(*secs).unwrap_or_else(|e| {
+                    error!("Invalid timeout {}: {}", secs, e);
+                    process::exit(1);
+                }),
+                None => ShairportMetadataReader::DEFAULT_READ_TIMEOUT,
+            };
+            match reader.read_metadata_with_timeout(timeout).await {
+                Ok(outcome) => {
+                    for metadata in &outcome.items {
+                        print_metadata(metadata);
+                    }
+                    if outcome.is_timeout() {
+                        info!("Stopped after {:?}, the source was still open", timeout);
                     }
                 }
                 Err(e) => {
@@ -120,24 +240,7 @@ async fn main() -> Result<()> {
     } else if continuous {
         info!("Starting continuous monitoring of: {}", metadata_path);
 
-        match reader.start_continuous_monitoring_from_file().await {
-            Ok(mut rx) => {
-                tokio::select! {
-                    _ = signal::ctrl_c() => {
-                        info!("Received Ctrl+C, shutting down...");
-                    }
-                    _ = async {
-                        while let Some(metadata) = rx.recv().await {
-                            print_metadata(&metadata);
-                        }
-                    } => {}
-                }
-            }
-            Err(e) => {
-                error!("Failed to start continuous monitoring: {}", e);
-                process::exit(1);
-            }
-        }
+        print_stream(reader.stream_file()).await;
     } else {
         info!("Reading metadata from pipe: {}", metadata_path);
         match reader.read_from_pipe().await {
@@ -156,69 +259,219 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+fn missing_path(matches: &clap::ArgMatches) -> Result<MissingPath> {
+    if matches.get_flag("wait-for-path") {
+        return Ok(MissingPath::Wait);
+    }
+    if !matches.get_flag("create-fifo") {
+        return Ok(MissingPath::Fail);
+    }
+
+    let mut options = FifoOptions::default();
+    if let Some(mode) = matches.get_one::<String>("fifo-mode") {
+        options.mode = u32::from_str_radix(mode, 8)
+            .map_err(|_| MetadataError::Parse(format!("invalid pipe mode '{}'", mode)))?;
+    }
+    if let Some(owner) = matches.get_one::<String>("fifo-owner") {
+        let invalid = || MetadataError::Parse(format!("invalid pipe owner '{}'", owner));
+        let (uid, gid) = match owner.split_once(':') {
+            Some((uid, gid)) => (uid, Some(gid)),
+            None => (owner.as_str(), None),
+        };
+        options.owner = Some(uid.parse().map_err(|_| invalid())?);
+        options.group = gid.map(|gid| gid.parse()).transpose().map_err(|_| invalid())?;
+    }
+    Ok(MissingPath::Create(options))
+}
+
+async fn udp_config(addr: &str, interface: Option<&String>) -> Result<UdpConfig> {
+    let addr = tokio::net::lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| MetadataError::Parse("address did not resolve".to_string()))?;
+    let mut config = UdpConfig::new(addr);
+    if let Some(interface) = interface {
+        if let Ok(v4) = interface.parse::<Ipv4Addr>() {
+            config.interface_v4 = v4;
+        } else {
+            config.interface_v6 = interface
+                .parse()
+                .map_err(|_| MetadataError::Parse(format!("invalid multicast interface '{}'", interface)))?;
+        }
+    }
+    Ok(config)
+}
+
+// Print a metadata stream until it ends or Ctrl+C, exiting on an error
+async fn print_stream(mut stream: MetadataStream) {
+    tokio::select! {
+        _ = signal::ctrl_c() => {
+            info!("Received Ctrl+C, shutting down...");
+        }
+        _ = async {
+            while let Some(metadata) = stream.next().await {
+                match metadata {
+                    Ok(metadata) => print_metadata(&metadata),
+                    Err(e) => {
+                        error!("Error in continuous reading: {}", e);
+                        process::exit(1);
+                    }
+                }
+            }
+        } => {}
+    }
+}
+
+// m:ss, or h:mm:ss from an hour
+fn format_duration(duration: &Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+// Suffix for an event's RTP time, if it has one
+fn format_rtp_time(time: &Option<u32>) -> String {
+    time.map(|time| format!(" (RTP {})", time)).unwrap_or_default()
+}
+
+// UTC date and time, e.g. 2024-03-01 12:34:56 UTC
+fn format_date(date: &SystemTime) -> String {
+    let Ok(since) = date.duration_since(UNIX_EPOCH) else {
+        return "before 1970".to_string();
+    };
+    let secs = since.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// Gregorian date from days since 1970-01-01 (Howard Hinnant's algorithm)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
 fn print_metadata(metadata: &ShairportMetadata) {
     match metadata {
         // Core metadata
         ShairportMetadata::Title(s) => println!("Title: {}", s),
         ShairportMetadata::Artist(s) => println!("Artist: {}", s),
         ShairportMetadata::Album(s) => println!("Album: {}", s),
+        ShairportMetadata::AlbumArtist(s) => println!("Album Artist: {}", s),
+        ShairportMetadata::AlbumId(n) => println!("Album ID: {}", n),
         ShairportMetadata::Genre(s) => println!("Genre: {}", s),
         ShairportMetadata::Year(s) => println!("Year: {}", s),
         ShairportMetadata::Comment(s) => println!("Comment: {}", s),
         ShairportMetadata::Composer(s) => println!("Composer: {}", s),
-        ShairportMetadata::Copyright(s) => println!("Copyright: {}", s),
+        ShairportMetadata::Compilation(n) => println!("Compilation: {}", if *n != 0 { "yes" } else { "no" }),
         ShairportMetadata::TrackNumber(s) => println!("Track Number: {}", s),
         ShairportMetadata::TrackCount(s) => println!("Track Count: {}", s),
         ShairportMetadata::DiscNumber(s) => println!("Disc Number: {}", s),
         ShairportMetadata::DiscCount(s) => println!("Disc Count: {}", s),
-        ShairportMetadata::TrackTime(s) => println!("Track Time: {}", s),
+        ShairportMetadata::TrackTime(d) => println!("Track Time: {}", format_duration(d)),
+        ShairportMetadata::StartTime(d) => println!("Start Time: {}", format_duration(d)),
         ShairportMetadata::SampleRate(s) => println!("Sample Rate: {}", s),
+        ShairportMetadata::Bitrate(n) => println!("Bitrate: {} kbps", n),
+        ShairportMetadata::BeatsPerMinute(n) => println!("BPM: {}", n),
+        ShairportMetadata::Size(n) => println!("Size: {} bytes", n),
+        ShairportMetadata::Format(s) => println!("Format: {}", s),
         ShairportMetadata::ItemId(s) => println!("Item ID: {}", s),
         ShairportMetadata::MediaKind(s) => println!("Media Kind: {}", s),
         ShairportMetadata::DataKind(s) => println!("Data Kind: {}", s),
-        ShairportMetadata::PersistentId(s) => println!("Persistent ID: {}", s),
+        ShairportMetadata::PersistentId(n) => println!("Persistent ID: {:016x}", n),
+        ShairportMetadata::Keywords(s) => println!("Keywords: {}", s),
         ShairportMetadata::SortTitle(s) => println!("Sort Title: {}", s),
         ShairportMetadata::SortArtist(s) => println!("Sort Artist: {}", s),
         ShairportMetadata::SortAlbum(s) => println!("Sort Album: {}", s),
         ShairportMetadata::SortComposer(s) => println!("Sort Composer: {}", s),
         ShairportMetadata::UserRating(s) => println!("User Rating: {}", s),
         ShairportMetadata::DataUrl(s) => println!("Data URL: {}", s),
-        ShairportMetadata::DateAdded(s) => println!("Date Added: {}", s),
-        ShairportMetadata::DateModified(s) => println!("Date Modified: {}", s),
-        ShairportMetadata::TimeStamp(s) => println!("Time Stamp: {}", s),
-        ShairportMetadata::Kind(s) => println!("Kind: {}", s),
+        ShairportMetadata::DateAdded(t) => println!("Date Added: {}", format_date(t)),
+        ShairportMetadata::DateModified(t) => println!("Date Modified: {}", format_date(t)),
+        ShairportMetadata::Description(s) => println!("Description: {}", s),
+        ShairportMetadata::LastSkipDate(t) => println!("Last Skip Date: {}", format_date(t)),
 
         // SSNC metadata
         ShairportMetadata::PlayBegin => println!("▶️  Play Begin"),
         ShairportMetadata::PlayEnd => println!("⏹️  Play End"),
         ShairportMetadata::PlayFlush => println!("🔄 Play Flush"),
         ShairportMetadata::PlayResume => println!("▶️  Play Resume"),
-        ShairportMetadata::PlayVolume(s) => println!("Play Volume: {}", s),
+        ShairportMetadata::PlayVolume(v) => {
+            if v.is_muted() {
+                println!("🔇 Volume: muted");
+            } else {
+                println!("🔊 Volume: {:.0}% ({:.2} dB)", v.percent(), v.volume_db);
+            }
+        }
         ShairportMetadata::StreamTitle(s) => println!("Stream Title: {}", s),
         ShairportMetadata::StreamName(s) => println!("Stream Name: {}", s),
         ShairportMetadata::UserAgent(s) => println!("User Agent: {}", s),
         ShairportMetadata::ActiveBegin => println!("🎵 Active Begin"),
         ShairportMetadata::ActiveEnd => println!("⏸️  Active End"),
+        ShairportMetadata::ServiceName(s) => println!("Service Name: {}", s),
+        ShairportMetadata::StreamType(s) => println!("Stream Type: {}", s),
+        ShairportMetadata::OutputFormat(s) => println!("Output Format: {}", s),
+        ShairportMetadata::OutputRate(n) => println!("Output Rate: {}", n),
+
+        // Session and connection
+        ShairportMetadata::ClientIp(ip) => println!("Client IP: {}", ip),
+        ShairportMetadata::ServerIp(ip) => println!("Server IP: {}", ip),
+        ShairportMetadata::ClientConnected(ip) => println!("🔗 Client Connected: {}", ip),
+        ShairportMetadata::ClientDisconnected(ip) => println!("👋 Client Disconnected: {}", ip),
+        ShairportMetadata::DacpId(s) => println!("DACP ID: {}", s),
+        ShairportMetadata::DacpPort(n) => println!("DACP Port: {}", n),
+        ShairportMetadata::ActiveRemote(s) => println!("Active Remote: {}", s),
+        ShairportMetadata::PlayDisconnect => println!("⏏️  Play Disconnect"),
 
-        // Progress and timing metadata (raw data - format not fully understood)
-        ShairportMetadata::Progress(s) => println!("⏱️  Progress: {}", s),
+        // Progress and timing metadata
+        ShairportMetadata::Progress(p) => println!(
+            "⏱️  Progress: {} / {} ({:.0}%)",
+            format_duration(&p.elapsed()),
+            format_duration(&p.duration()),
+            p.fraction() * 100.0
+        ),
         ShairportMetadata::MetadataStart(s) => println!("📅 Metadata Start: {}", s),
         ShairportMetadata::MetadataEnd(s) => println!("📅 Metadata End: {}", s),
-
-        // Core capabilities and player info
-        ShairportMetadata::Capabilities(s) => {
-            if s.is_empty() {
-                println!("⚙️  Capabilities: (empty)");
-            } else {
-                println!("⚙️  Capabilities: {}", s);
-            }
+        ShairportMetadata::PictureStart(t) => println!("🖼️  Picture Start{}", format_rtp_time(t)),
+        ShairportMetadata::PictureEnd(t) => println!("🖼️  Picture End{}", format_rtp_time(t)),
+        ShairportMetadata::FirstFrame(t) => println!("🎬 First Frame{}", format_rtp_time(t)),
+        ShairportMetadata::FlushRequest(t) => println!("🔄 Flush Request{}", format_rtp_time(t)),
+        ShairportMetadata::FramePosition(f) => println!("💓 Frame {} at {} ns", f.frame, f.time_ns),
+        ShairportMetadata::FirstFramePosition(f) => {
+            println!("💓 First Frame {} at {} ns", f.frame, f.time_ns)
         }
-        ShairportMetadata::MediaPlayer(data) => {
-            println!("🎯 Media Player: {} bytes (hex: {:02x?})", data.len(), data);
+
+        // DACP player state
+        ShairportMetadata::PlayStatus(n) => {
+            let status = match n {
+                2 => "stopped",
+                3 => "paused",
+                4 => "playing",
+                _ => "unknown",
+            };
+            println!("⚙️  Play Status: {} ({})", status, n);
         }
 
         // Picture data
-        ShairportMetadata::Picture(data) => {
+        ShairportMetadata::Picture { data, .. } => {
             println!("🖼️  Picture: {} bytes", data.len());
             if data.len() >= 4 {
                 let format = match &data[0..4] {
@@ -273,5 +526,12 @@ fn print_metadata(metadata: &ShairportMetadata) {
                 );
             }
         }
+
+        // Junk and malformed items skipped by the parser
+        ShairportMetadata::Resynchronized { skipped, offset } => {
+            println!("⚠️  Resynchronized: skipped {} bytes, resuming at offset {}", skipped, offset)
+        }
+        ShairportMetadata::Diagnostic { message, .. } => println!("⚠️  Skipped item: {}", message),
+        ShairportMetadata::SourceReconnected => println!("🔌 Source reconnected"),
     }
 }
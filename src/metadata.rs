@@ -1,7 +1,17 @@
-#[derive(Debug, Clone)]
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::daap::{self, DaapValue, Timestamp};
+use crate::error::MetadataError;
+use crate::fourcc::FourCC;
+use crate::ssnc::{FrameTime, Progress, Volume};
+use core::net::IpAddr;
+use core::str::FromStr;
+use core::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MetadataItem {
-    pub item_type: String,
-    pub code: String,
+    pub item_type: FourCC,
+    pub code: FourCC,
     pub data: Vec<u8>,
 }
 
@@ -11,140 +21,367 @@ pub enum ShairportMetadata {
     Title(String),
     Artist(String),
     Album(String),
+    AlbumArtist(String),
+    AlbumId(u64),
     Genre(String),
-    Year(String),
+    Year(u16),
     Comment(String),
     Composer(String),
-    Copyright(String),
-    TrackNumber(String),
-    TrackCount(String),
-    DiscNumber(String),
-    DiscCount(String),
-    TrackTime(String),
-    SampleRate(String),
-    ItemId(String),
-    MediaKind(String),
-    DataKind(String),
-    PersistentId(String),
+    Compilation(u8),
+    TrackNumber(u16),
+    TrackCount(u16),
+    DiscNumber(u16),
+    DiscCount(u16),
+    TrackTime(Duration),
+    StartTime(Duration),
+    SampleRate(u32),
+    Bitrate(u16),
+    BeatsPerMinute(u16),
+    Size(u32),
+    Format(String),
+    ItemId(u32),
+    MediaKind(u8),
+    DataKind(u8),
+    PersistentId(u64),
+    Keywords(String),
     SortTitle(String),
     SortArtist(String),
     SortAlbum(String),
     SortComposer(String),
-    UserRating(String),
+    UserRating(u8),
     DataUrl(String),
-    DateAdded(String),
-    DateModified(String),
-    TimeStamp(String),
-    Kind(String),
+    DateAdded(Timestamp),
+    DateModified(Timestamp),
+    Description(String),
+    LastSkipDate(Timestamp),
 
     // SSNC metadata (playback control)
     PlayBegin,
     PlayEnd,
     PlayFlush,
     PlayResume,
-    PlayVolume(String),
+    PlayVolume(Volume),
     StreamTitle(String),
     StreamName(String),
     UserAgent(String),
     ActiveBegin,
     ActiveEnd,
-    
+    ServiceName(String),        // ssnc:svna - name shairport-sync advertises
+    StreamType(String),         // ssnc:styp - "Realtime" or "Buffered"
+    OutputFormat(String),       // ssnc:ofmt - e.g. "S16_LE"
+    OutputRate(u32),            // ssnc:ofps - output frames per second
+
+    // Session and connection
+    ClientIp(IpAddr),           // ssnc:clip - address of the sender
+    ServerIp(IpAddr),           // ssnc:svip - address the sender connected to
+    ClientConnected(IpAddr),    // ssnc:conn - a sender connected
+    ClientDisconnected(IpAddr), // ssnc:disc - a sender disconnected
+    DacpId(String),             // ssnc:daid - DACP-ID of the sender, for remote control
+    DacpPort(u16),              // ssnc:dapo - port of the sender's DACP service
+    ActiveRemote(String),       // ssnc:acre - Active-Remote token for DACP requests
+    PlayDisconnect,             // ssnc:pdis - the play session was disconnected
+
+    // RTP times are `None` when the sender didn't give one
+    PictureStart(Option<u32>),  // ssnc:pcst - a picture is about to be sent
+    PictureEnd(Option<u32>),    // ssnc:pcen - a picture was sent
+    FirstFrame(Option<u32>),    // ssnc:pffr - first frame of a play session received
+    FlushRequest(Option<u32>),  // ssnc:flsr - a flush was requested
+    FramePosition(FrameTime),   // ssnc:phbt - heartbeat with the frame being played
+    FirstFramePosition(FrameTime), // ssnc:phb0 - when the first frame is played
+
     // Progress and timing metadata
-    Progress(String),           // ssnc:prgr - playback progress (current/total/end)
+    Progress(Progress),         // ssnc:prgr - playback progress (start/current/end)
     MetadataStart(String),      // ssnc:mdst - metadata start time
     MetadataEnd(String),        // ssnc:mden - metadata end time
     
-    // Core capabilities and player info
-    Capabilities(String),       // core:caps - device capabilities
-    MediaPlayer(Vec<u8>),       // core:mper - media player info
+    // DACP player state
+    PlayStatus(u8),             // core:caps - 2 stopped, 3 paused, 4 playing
 
-    // Picture data
-    Picture(Vec<u8>),
+    // Picture data, with the type/code it came in (shairport-sync uses ssnc:PICT)
+    Picture {
+        item_type: FourCC,
+        code: FourCC,
+        data: Vec<u8>,
+    },
 
     // Other/unknown metadata
     Other {
-        item_type: String,
-        code: String,
+        item_type: FourCC,
+        code: FourCC,
         data: Vec<u8>,
     },
+
+    // Junk skipped by a parser to find the next item, resuming at `offset`
+    Resynchronized {
+        skipped: usize,
+        offset: u64,
+    },
+
+    // Malformed item skipped by a lenient parser
+    Diagnostic {
+        item_type: FourCC,
+        code: FourCC,
+        offset: u64,
+        message: String,
+    },
+
+    // The writer closed the named pipe and it was reopened, items sent in
+    // between may be missing
+    SourceReconnected,
 }
 
 impl ShairportMetadata {
     pub fn from_item(item: &MetadataItem) -> Self {
-        // Try to decode data as UTF-8 string, or fall back to hex representation
-        let data_str = if item.data.is_empty() {
-            String::new()
+        // Picture data - try multiple possible codes
+        if let (b"ssnc", b"pict") | (b"ssnc", b"PICT") | (b"pict", _) | (b"core", b"PICT") =
+            (item.item_type.as_bytes(), item.code.as_bytes())
+        {
+            return Self::Picture {
+                item_type: item.item_type,
+                code: item.code,
+                data: item.data.clone(),
+            };
+        }
+
+        // DAAP values are decoded by the type their code has in the table.
+        // Codes not in it and values that don't decode are `Other`.
+        if item.item_type == FourCC::CORE {
+            return daap::daap_type(item.code)
+                .and_then(|daap_type| DaapValue::decode(daap_type, &item.data))
+                .and_then(|value| Self::from_daap_value(item.code, value))
+                .unwrap_or_else(|| Self::other(item));
+        }
+
+        // Everything else is text, a payload that isn't UTF-8 is kept as it is
+        let Ok(data_str) = String::from_utf8(item.data.clone()) else {
+            return Self::other(item);
+        };
+
+        match (item.item_type.as_bytes(), item.code.as_bytes()) {
+            // SSNC metadata mappings
+            (b"ssnc", b"pbeg") => Self::PlayBegin,
+            (b"ssnc", b"pend") => Self::PlayEnd,
+            (b"ssnc", b"pfls") => Self::PlayFlush,
+            (b"ssnc", b"prsm") => Self::PlayResume,
+            (b"ssnc", b"pvol") => Self::from_text(item, &data_str, Self::PlayVolume),
+            (b"ssnc", b"stal") => Self::StreamTitle(data_str),
+            (b"ssnc", b"snam") => Self::StreamName(data_str),
+            (b"ssnc", b"snua") => Self::UserAgent(data_str),
+            (b"ssnc", b"abeg") => Self::ActiveBegin,
+            (b"ssnc", b"aend") => Self::ActiveEnd,
+            (b"ssnc", b"svna") => Self::ServiceName(data_str),
+            (b"ssnc", b"styp") => Self::StreamType(data_str),
+            (b"ssnc", b"ofmt") => Self::OutputFormat(data_str),
+            (b"ssnc", b"ofps") => Self::from_text(item, &data_str, Self::OutputRate),
+
+            // Session and connection
+            (b"ssnc", b"clip") => Self::from_text(item, &data_str, Self::ClientIp),
+            (b"ssnc", b"svip") => Self::from_text(item, &data_str, Self::ServerIp),
+            (b"ssnc", b"conn") => Self::from_text(item, &data_str, Self::ClientConnected),
+            (b"ssnc", b"disc") => Self::from_text(item, &data_str, Self::ClientDisconnected),
+            (b"ssnc", b"daid") => Self::DacpId(data_str),
+            (b"ssnc", b"dapo") => Self::from_text(item, &data_str, Self::DacpPort),
+            (b"ssnc", b"acre") => Self::ActiveRemote(data_str),
+            (b"ssnc", b"pdis") => Self::PlayDisconnect,
+
+            // Progress and timing metadata
+            (b"ssnc", b"prgr") => Self::from_text(item, &data_str, Self::Progress),
+            (b"ssnc", b"mdst") => Self::MetadataStart(data_str),
+            (b"ssnc", b"mden") => Self::MetadataEnd(data_str),
+            (b"ssnc", b"pcst") => Self::from_rtp_time(item, &data_str, Self::PictureStart),
+            (b"ssnc", b"pcen") => Self::from_rtp_time(item, &data_str, Self::PictureEnd),
+            (b"ssnc", b"pffr") => Self::from_rtp_time(item, &data_str, Self::FirstFrame),
+            (b"ssnc", b"flsr") => Self::from_rtp_time(item, &data_str, Self::FlushRequest),
+            (b"ssnc", b"phbt") => Self::from_text(item, &data_str, Self::FramePosition),
+            (b"ssnc", b"phb0") => Self::from_text(item, &data_str, Self::FirstFramePosition),
+
+            // Unknown/other metadata
+            _ => Self::other(item),
+        }
+    }
+
+    fn other(item: &MetadataItem) -> Self {
+        Self::Other {
+            item_type: item.item_type,
+            code: item.code,
+            data: item.data.clone(),
+        }
+    }
+
+    // Variant for a value sent as text, `Other` if it doesn't parse
+    fn from_text<T: FromStr>(item: &MetadataItem, text: &str, variant: impl FnOnce(T) -> Self) -> Self {
+        text.parse().map_or_else(|_| Self::other(item), variant)
+    }
+
+    // An RTP time sent as decimal text, possibly left out
+    fn from_rtp_time(item: &MetadataItem, text: &str, variant: fn(Option<u32>) -> Self) -> Self {
+        if text.is_empty() {
+            variant(None)
         } else {
-            String::from_utf8(item.data.clone()).unwrap_or_else(|_| {
-                // If not valid UTF-8, show as hex
-                item.data
-                    .iter()
-                    .map(|b| format!("{:02x}", b))
-                    .collect::<String>()
+            Self::from_text(item, text, |time| variant(Some(time)))
+        }
+    }
+
+    // Variant for a decoded `core` value. Each code in the DAAP table needs an
+    // arm with the value its type decodes to.
+    fn from_daap_value(code: FourCC, value: DaapValue) -> Option<Self> {
+        use DaapValue::{Byte, Date, Int, Long, Short};
+
+        Some(match (code.as_bytes(), value) {
+            (b"minm", DaapValue::String(s)) => Self::Title(s),
+            (b"asar", DaapValue::String(s)) => Self::Artist(s),
+            (b"asal", DaapValue::String(s)) => Self::Album(s),
+            (b"asaa", DaapValue::String(s)) => Self::AlbumArtist(s),
+            (b"asai", Long(n)) => Self::AlbumId(n),
+            (b"asgn", DaapValue::String(s)) => Self::Genre(s),
+            (b"asyr", Short(n)) => Self::Year(n),
+            (b"ascm", DaapValue::String(s)) => Self::Comment(s),
+            (b"ascp", DaapValue::String(s)) => Self::Composer(s),
+            (b"asco", Byte(n)) => Self::Compilation(n),
+            (b"astn", Short(n)) => Self::TrackNumber(n),
+            (b"astc", Short(n)) => Self::TrackCount(n),
+            (b"asdn", Short(n)) => Self::DiscNumber(n),
+            (b"asdc", Short(n)) => Self::DiscCount(n),
+            (b"astm", Int(ms)) => Self::TrackTime(Duration::from_millis(ms.into())),
+            (b"asst", Int(ms)) => Self::StartTime(Duration::from_millis(ms.into())),
+            (b"assr", Int(n)) => Self::SampleRate(n),
+            (b"asbr", Short(n)) => Self::Bitrate(n),
+            (b"asbt", Short(n)) => Self::BeatsPerMinute(n),
+            (b"assz", Int(n)) => Self::Size(n),
+            (b"asfm", DaapValue::String(s)) => Self::Format(s),
+            (b"miid", Int(n)) => Self::ItemId(n),
+            (b"mikd", Byte(n)) => Self::MediaKind(n),
+            (b"asdk", Byte(n)) => Self::DataKind(n),
+            (b"mper", Long(n)) => Self::PersistentId(n),
+            (b"asky", DaapValue::String(s)) => Self::Keywords(s),
+            (b"assn", DaapValue::String(s)) => Self::SortTitle(s),
+            (b"assa", DaapValue::String(s)) => Self::SortArtist(s),
+            (b"assu", DaapValue::String(s)) => Self::SortAlbum(s),
+            (b"assc", DaapValue::String(s)) => Self::SortComposer(s),
+            (b"asur", Byte(n)) => Self::UserRating(n),
+            (b"asul", DaapValue::String(s)) => Self::DataUrl(s),
+            (b"asda", Date(t)) => Self::DateAdded(t),
+            (b"asdm", Date(t)) => Self::DateModified(t),
+            (b"asdt", DaapValue::String(s)) => Self::Description(s),
+            (b"askd", Date(t)) => Self::LastSkipDate(t),
+            (b"caps", Byte(n)) => Self::PlayStatus(n),
+            _ => return None,
+        })
+    }
+
+    /// The item this metadata was decoded from, for writing it back out.
+    /// Parser events have no item.
+    pub fn to_item(&self) -> Option<MetadataItem> {
+        let item = |item_type: &[u8; 4], code: &[u8; 4], data: Vec<u8>| {
+            Some(MetadataItem {
+                item_type: FourCC::new(item_type),
+                code: FourCC::new(code),
+                data,
             })
         };
 
-        match (item.item_type.as_str(), item.code.as_str()) {
-            // Core metadata mappings
-            ("core", "minm") => Self::Title(data_str),
-            ("core", "asar") => Self::Artist(data_str),
-            ("core", "asal") => Self::Album(data_str),
-            ("core", "asgn") => Self::Genre(data_str),
-            ("core", "asyr") => Self::Year(data_str),
-            ("core", "ascm") => Self::Comment(data_str),
-            ("core", "asco") => Self::Composer(data_str),
-            ("core", "ascp") => Self::Copyright(data_str),
-            ("core", "astn") => Self::TrackNumber(data_str),
-            ("core", "astc") => Self::TrackCount(data_str),
-            ("core", "asdn") => Self::DiscNumber(data_str),
-            ("core", "asdc") => Self::DiscCount(data_str),
-            ("core", "asdt") => Self::TrackTime(data_str),
-            ("core", "assr") => Self::SampleRate(data_str),
-            ("core", "miid") => Self::ItemId(data_str),
-            ("core", "mikd") => Self::MediaKind(data_str),
-            ("core", "asky") => Self::DataKind(data_str),
-            ("core", "aspl") => Self::PersistentId(data_str),
-            ("core", "asst") => Self::SortTitle(data_str),
-            ("core", "assa") => Self::SortArtist(data_str),
-            ("core", "assu") => Self::SortAlbum(data_str),
-            ("core", "assc") => Self::SortComposer(data_str),
-            ("core", "asur") => Self::UserRating(data_str),
-            ("core", "asul") => Self::DataUrl(data_str),
-            ("core", "asda") => Self::DateAdded(data_str),
-            ("core", "asdm") => Self::DateModified(data_str),
-            ("core", "astm") => Self::TimeStamp(data_str),
-            ("core", "askd") => Self::Kind(data_str),
+        let core = |code: &[u8; 4], value: DaapValue| item(b"core", code, value.to_bytes());
+
+        match self {
+            // Core metadata
+            Self::Title(s) => core(b"minm", DaapValue::String(s.clone())),
+            Self::Artist(s) => core(b"asar", DaapValue::String(s.clone())),
+            Self::Album(s) => core(b"asal", DaapValue::String(s.clone())),
+            Self::AlbumArtist(s) => core(b"asaa", DaapValue::String(s.clone())),
+            Self::AlbumId(n) => core(b"asai", DaapValue::Long(*n)),
+            Self::Genre(s) => core(b"asgn", DaapValue::String(s.clone())),
+            Self::Year(n) => core(b"asyr", DaapValue::Short(*n)),
+            Self::Comment(s) => core(b"ascm", DaapValue::String(s.clone())),
+            Self::Composer(s) => core(b"ascp", DaapValue::String(s.clone())),
+            Self::Compilation(n) => core(b"asco", DaapValue::Byte(*n)),
+            Self::TrackNumber(n) => core(b"astn", DaapValue::Short(*n)),
+            Self::TrackCount(n) => core(b"astc", DaapValue::Short(*n)),
+            Self::DiscNumber(n) => core(b"asdn", DaapValue::Short(*n)),
+            Self::DiscCount(n) => core(b"asdc", DaapValue::Short(*n)),
+            Self::TrackTime(d) => core(b"astm", DaapValue::Int(daap::millis(d))),
+            Self::StartTime(d) => core(b"asst", DaapValue::Int(daap::millis(d))),
+            Self::SampleRate(n) => core(b"assr", DaapValue::Int(*n)),
+            Self::Bitrate(n) => core(b"asbr", DaapValue::Short(*n)),
+            Self::BeatsPerMinute(n) => core(b"asbt", DaapValue::Short(*n)),
+            Self::Size(n) => core(b"assz", DaapValue::Int(*n)),
+            Self::Format(s) => core(b"asfm", DaapValue::String(s.clone())),
+            Self::ItemId(n) => core(b"miid", DaapValue::Int(*n)),
+            Self::MediaKind(n) => core(b"mikd", DaapValue::Byte(*n)),
+            Self::DataKind(n) => core(b"asdk", DaapValue::Byte(*n)),
+            Self::PersistentId(n) => core(b"mper", DaapValue::Long(*n)),
+            Self::Keywords(s) => core(b"asky", DaapValue::String(s.clone())),
+            Self::SortTitle(s) => core(b"assn", DaapValue::String(s.clone())),
+            Self::SortArtist(s) => core(b"assa", DaapValue::String(s.clone())),
+            Self::SortAlbum(s) => core(b"assu", DaapValue::String(s.clone())),
+            Self::SortComposer(s) => core(b"assc", DaapValue::String(s.clone())),
+            Self::UserRating(n) => core(b"asur", DaapValue::Byte(*n)),
+            Self::DataUrl(s) => core(b"asul", DaapValue::String(s.clone())),
+            Self::DateAdded(t) => core(b"asda", DaapValue::Date(*t)),
+            Self::DateModified(t) => core(b"asdm", DaapValue::Date(*t)),
+            Self::Description(s) => core(b"asdt", DaapValue::String(s.clone())),
+            Self::LastSkipDate(t) => core(b"askd", DaapValue::Date(*t)),
+            Self::PlayStatus(n) => core(b"caps", DaapValue::Byte(*n)),
+
+            // SSNC metadata
+            Self::PlayBegin => item(b"ssnc", b"pbeg", Vec::new()),
+            Self::PlayEnd => item(b"ssnc", b"pend", Vec::new()),
+            Self::PlayFlush => item(b"ssnc", b"pfls", Vec::new()),
+            Self::PlayResume => item(b"ssnc", b"prsm", Vec::new()),
+            Self::PlayVolume(v) => item(b"ssnc", b"pvol", v.to_string().into_bytes()),
+            Self::StreamTitle(s) => item(b"ssnc", b"stal", s.as_bytes().to_vec()),
+            Self::StreamName(s) => item(b"ssnc", b"snam", s.as_bytes().to_vec()),
+            Self::UserAgent(s) => item(b"ssnc", b"snua", s.as_bytes().to_vec()),
+            Self::ActiveBegin => item(b"ssnc", b"abeg", Vec::new()),
+            Self::ActiveEnd => item(b"ssnc", b"aend", Vec::new()),
+            Self::ServiceName(s) => item(b"ssnc", b"svna", s.as_bytes().to_vec()),
+            Self::StreamType(s) => item(b"ssnc", b"styp", s.as_bytes().to_vec()),
+            Self::OutputFormat(s) => item(b"ssnc", b"ofmt", s.as_bytes().to_vec()),
+            Self::OutputRate(n) => item(b"ssnc", b"ofps", n.to_string().into_bytes()),
+
+            // Session and connection
+            Self::ClientIp(ip) => item(b"ssnc", b"clip", ip.to_string().into_bytes()),
+            Self::ServerIp(ip) => item(b"ssnc", b"svip", ip.to_string().into_bytes()),
+            Self::ClientConnected(ip) => item(b"ssnc", b"conn", ip.to_string().into_bytes()),
+            Self::ClientDisconnected(ip) => item(b"ssnc", b"disc", ip.to_string().into_bytes()),
+            Self::DacpId(s) => item(b"ssnc", b"daid", s.as_bytes().to_vec()),
+            Self::DacpPort(n) => item(b"ssnc", b"dapo", n.to_string().into_bytes()),
+            Self::ActiveRemote(s) => item(b"ssnc", b"acre", s.as_bytes().to_vec()),
+            Self::PlayDisconnect => item(b"ssnc", b"pdis", Vec::new()),
 
-            // SSNC metadata mappings
-            ("ssnc", "pbeg") => Self::PlayBegin,
-            ("ssnc", "pend") => Self::PlayEnd,
-            ("ssnc", "pfls") => Self::PlayFlush,
-            ("ssnc", "prsm") => Self::PlayResume,
-            ("ssnc", "pvol") => Self::PlayVolume(data_str),
-            ("ssnc", "stal") => Self::StreamTitle(data_str),
-            ("ssnc", "snam") => Self::StreamName(data_str),
-            ("ssnc", "snua") => Self::UserAgent(data_str),
-            ("ssnc", "abeg") => Self::ActiveBegin,
-            ("ssnc", "aend") => Self::ActiveEnd,
-            
             // Progress and timing metadata
-            ("ssnc", "prgr") => Self::Progress(data_str),
-            ("ssnc", "mdst") => Self::MetadataStart(data_str),
-            ("ssnc", "mden") => Self::MetadataEnd(data_str),
-            
-            // Core capabilities and player info
-            ("core", "caps") => Self::Capabilities(data_str),
-            ("core", "mper") => Self::MediaPlayer(item.data.clone()),
+            Self::Progress(p) => item(b"ssnc", b"prgr", p.to_string().into_bytes()),
+            Self::MetadataStart(s) => item(b"ssnc", b"mdst", s.as_bytes().to_vec()),
+            Self::MetadataEnd(s) => item(b"ssnc", b"mden", s.as_bytes().to_vec()),
+            Self::PictureStart(t) => item(b"ssnc", b"pcst", rtp_time_string(t).into_bytes()),
+            Self::PictureEnd(t) => item(b"ssnc", b"pcen", rtp_time_string(t).into_bytes()),
+            Self::FirstFrame(t) => item(b"ssnc", b"pffr", rtp_time_string(t).into_bytes()),
+            Self::FlushRequest(t) => item(b"ssnc", b"flsr", rtp_time_string(t).into_bytes()),
+            Self::FramePosition(f) => item(b"ssnc", b"phbt", f.to_string().into_bytes()),
+            Self::FirstFramePosition(f) => item(b"ssnc", b"phb0", f.to_string().into_bytes()),
 
-            // Picture data - try multiple possible codes
-            ("ssnc", "pict") | ("pict", _) | ("core", "PICT") => Self::Picture(item.data.clone()),
 
-            // Unknown/other metadata
-            _ => Self::Other {
-                item_type: item.item_type.clone(),
-                code: item.code.clone(),
-                data: item.data.clone(),
-            },
+            Self::Picture { item_type, code, data } => item(&item_type.0, &code.0, data.clone()),
+            Self::Other { item_type, code, data } => item(&item_type.0, &code.0, data.clone()),
+            Self::Resynchronized { .. } | Self::Diagnostic { .. } | Self::SourceReconnected => None,
+        }
+    }
+
+    /// Diagnostic event for an item-level parse error, `None` for other errors
+    pub fn from_error(error: &MetadataError) -> Option<Self> {
+        match error {
+            MetadataError::LengthMismatch { item_type, code, offset, .. }
+            | MetadataError::InvalidBase64 { item_type, code, offset, .. }
+            | MetadataError::InvalidField { item_type, code, offset, .. }
+            | MetadataError::MissingField { item_type, code, offset, .. }
+            | MetadataError::UnexpectedTag { item_type, code, offset, .. }
+            | MetadataError::ItemTooLarge { item_type, code, offset, .. }
+            | MetadataError::BufferOverflow { item_type, code, offset, .. } => Some(Self::Diagnostic {
+                item_type: *item_type,
+                code: *code,
+                offset: *offset,
+                message: error.to_string(),
+            }),
+            _ => None,
         }
     }
 
@@ -153,21 +390,29 @@ impl ShairportMetadata {
             Self::Title(_) => "Title",
             Self::Artist(_) => "Artist",
             Self::Album(_) => "Album",
+            Self::AlbumArtist(_) => "AlbumArtist",
+            Self::AlbumId(_) => "AlbumId",
             Self::Genre(_) => "Genre",
             Self::Year(_) => "Year",
             Self::Comment(_) => "Comment",
             Self::Composer(_) => "Composer",
-            Self::Copyright(_) => "Copyright",
+            Self::Compilation(_) => "Compilation",
             Self::TrackNumber(_) => "TrackNumber",
             Self::TrackCount(_) => "TrackCount",
             Self::DiscNumber(_) => "DiscNumber",
             Self::DiscCount(_) => "DiscCount",
             Self::TrackTime(_) => "TrackTime",
+            Self::StartTime(_) => "StartTime",
             Self::SampleRate(_) => "SampleRate",
+            Self::Bitrate(_) => "Bitrate",
+            Self::BeatsPerMinute(_) => "BeatsPerMinute",
+            Self::Size(_) => "Size",
+            Self::Format(_) => "Format",
             Self::ItemId(_) => "ItemId",
             Self::MediaKind(_) => "MediaKind",
             Self::DataKind(_) => "DataKind",
             Self::PersistentId(_) => "PersistentId",
+            Self::Keywords(_) => "Keywords",
             Self::SortTitle(_) => "SortTitle",
             Self::SortArtist(_) => "SortArtist",
             Self::SortAlbum(_) => "SortAlbum",
@@ -176,8 +421,8 @@ impl ShairportMetadata {
             Self::DataUrl(_) => "DataUrl",
             Self::DateAdded(_) => "DateAdded",
             Self::DateModified(_) => "DateModified",
-            Self::TimeStamp(_) => "TimeStamp",
-            Self::Kind(_) => "Kind",
+            Self::Description(_) => "Description",
+            Self::LastSkipDate(_) => "LastSkipDate",
             Self::PlayBegin => "PlayBegin",
             Self::PlayEnd => "PlayEnd",
             Self::PlayFlush => "PlayFlush",
@@ -188,13 +433,33 @@ impl ShairportMetadata {
             Self::UserAgent(_) => "UserAgent",
             Self::ActiveBegin => "ActiveBegin",
             Self::ActiveEnd => "ActiveEnd",
+            Self::ServiceName(_) => "ServiceName",
+            Self::StreamType(_) => "StreamType",
+            Self::OutputFormat(_) => "OutputFormat",
+            Self::OutputRate(_) => "OutputRate",
+            Self::ClientIp(_) => "ClientIp",
+            Self::ServerIp(_) => "ServerIp",
+            Self::ClientConnected(_) => "ClientConnected",
+            Self::ClientDisconnected(_) => "ClientDisconnected",
+            Self::DacpId(_) => "DacpId",
+            Self::DacpPort(_) => "DacpPort",
+            Self::ActiveRemote(_) => "ActiveRemote",
+            Self::PlayDisconnect => "PlayDisconnect",
             Self::Progress(_) => "Progress",
             Self::MetadataStart(_) => "MetadataStart", 
             Self::MetadataEnd(_) => "MetadataEnd",
-            Self::Capabilities(_) => "Capabilities",
-            Self::MediaPlayer(_) => "MediaPlayer",
-            Self::Picture(_) => "Picture",
+            Self::PictureStart(_) => "PictureStart",
+            Self::PictureEnd(_) => "PictureEnd",
+            Self::FirstFrame(_) => "FirstFrame",
+            Self::FlushRequest(_) => "FlushRequest",
+            Self::FramePosition(_) => "FramePosition",
+            Self::FirstFramePosition(_) => "FirstFramePosition",
+            Self::PlayStatus(_) => "PlayStatus",
+            Self::Picture { .. } => "Picture",
             Self::Other { .. } => "Other",
+            Self::Resynchronized { .. } => "Resynchronized",
+            Self::Diagnostic { .. } => "Diagnostic",
+            Self::SourceReconnected => "SourceReconnected",
         }
     }
 
@@ -203,48 +468,170 @@ impl ShairportMetadata {
             Self::Title(s) => s.clone(),
             Self::Artist(s) => s.clone(),
             Self::Album(s) => s.clone(),
+            Self::AlbumArtist(s) => s.clone(),
+            Self::AlbumId(n) => n.to_string(),
             Self::Genre(s) => s.clone(),
-            Self::Year(s) => s.clone(),
+            Self::Year(n) => n.to_string(),
             Self::Comment(s) => s.clone(),
             Self::Composer(s) => s.clone(),
-            Self::Copyright(s) => s.clone(),
-            Self::TrackNumber(s) => s.clone(),
-            Self::TrackCount(s) => s.clone(),
-            Self::DiscNumber(s) => s.clone(),
-            Self::DiscCount(s) => s.clone(),
-            Self::TrackTime(s) => s.clone(),
-            Self::SampleRate(s) => s.clone(),
-            Self::ItemId(s) => s.clone(),
-            Self::MediaKind(s) => s.clone(),
-            Self::DataKind(s) => s.clone(),
-            Self::PersistentId(s) => s.clone(),
+            Self::Compilation(n) => n.to_string(),
+            Self::TrackNumber(n) => n.to_string(),
+            Self::TrackCount(n) => n.to_string(),
+            Self::DiscNumber(n) => n.to_string(),
+            Self::DiscCount(n) => n.to_string(),
+            Self::TrackTime(d) => d.as_millis().to_string(),
+            Self::StartTime(d) => d.as_millis().to_string(),
+            Self::SampleRate(n) => n.to_string(),
+            Self::Bitrate(n) => n.to_string(),
+            Self::BeatsPerMinute(n) => n.to_string(),
+            Self::Size(n) => n.to_string(),
+            Self::Format(s) => s.clone(),
+            Self::ItemId(n) => n.to_string(),
+            Self::MediaKind(n) => n.to_string(),
+            Self::DataKind(n) => n.to_string(),
+            Self::PersistentId(n) => n.to_string(),
+            Self::Keywords(s) => s.clone(),
             Self::SortTitle(s) => s.clone(),
             Self::SortArtist(s) => s.clone(),
             Self::SortAlbum(s) => s.clone(),
             Self::SortComposer(s) => s.clone(),
-            Self::UserRating(s) => s.clone(),
+            Self::UserRating(n) => n.to_string(),
             Self::DataUrl(s) => s.clone(),
-            Self::DateAdded(s) => s.clone(),
-            Self::DateModified(s) => s.clone(),
-            Self::TimeStamp(s) => s.clone(),
-            Self::Kind(s) => s.clone(),
+            Self::DateAdded(t) => date_string(t),
+            Self::DateModified(t) => date_string(t),
+            Self::Description(s) => s.clone(),
+            Self::LastSkipDate(t) => date_string(t),
             Self::PlayBegin => String::from("PlayBegin"),
             Self::PlayEnd => String::from("PlayEnd"),
             Self::PlayFlush => String::from("PlayFlush"),
             Self::PlayResume => String::from("PlayResume"),
-            Self::PlayVolume(s) => s.clone(),
+            Self::PlayVolume(v) => v.to_string(),
             Self::StreamTitle(s) => s.clone(),
             Self::StreamName(s) => s.clone(),
             Self::UserAgent(s) => s.clone(),
             Self::ActiveBegin => String::from("ActiveBegin"),
             Self::ActiveEnd => String::from("ActiveEnd"),
-            Self::Progress(s) => s.clone(),
+            Self::ServiceName(s) => s.clone(),
+            Self::StreamType(s) => s.clone(),
+            Self::OutputFormat(s) => s.clone(),
+            Self::OutputRate(n) => n.to_string(),
+            Self::ClientIp(ip) => ip.to_string(),
+            Self::ServerIp(ip) => ip.to_string(),
+            Self::ClientConnected(ip) => ip.to_string(),
+            Self::ClientDisconnected(ip) => ip.to_string(),
+            Self::DacpId(s) => s.clone(),
+            Self::DacpPort(n) => n.to_string(),
+            Self::ActiveRemote(s) => s.clone(),
+            Self::PlayDisconnect => String::from("PlayDisconnect"),
+            Self::Progress(p) => p.to_string(),
             Self::MetadataStart(s) => s.clone(),
             Self::MetadataEnd(s) => s.clone(),
-            Self::Capabilities(s) => s.clone(),
-            Self::MediaPlayer(_) => String::from("MediaPlayer"),
-            Self::Picture(_) => String::from("Picture"),
+            Self::PictureStart(t) => rtp_time_string(t),
+            Self::PictureEnd(t) => rtp_time_string(t),
+            Self::FirstFrame(t) => rtp_time_string(t),
+            Self::FlushRequest(t) => rtp_time_string(t),
+            Self::FramePosition(f) => f.to_string(),
+            Self::FirstFramePosition(f) => f.to_string(),
+            Self::PlayStatus(n) => n.to_string(),
+            Self::Picture { .. } => String::from("Picture"),
             Self::Other { .. } => String::from("Other"),
+            Self::Resynchronized { skipped, .. } => skipped.to_string(),
+            Self::Diagnostic { message, .. } => message.clone(),
+            Self::SourceReconnected => String::from("SourceReconnected"),
+        }
+    }
+}
+
+// Empty when no RTP time was sent
+fn rtp_time_string(time: &Option<u32>) -> String {
+    time.map(|time| time.to_string()).unwrap_or_default()
+}
+
+// Seconds since the Unix epoch, negative before it
+fn date_string(date: &Timestamp) -> String {
+    #[cfg(feature = "std")]
+    match date.duration_since(std::time::UNIX_EPOCH) {
+        Ok(since) => since.as_secs().to_string(),
+        Err(e) => format!("-{}", e.duration().as_secs()),
+    }
+    #[cfg(not(feature = "std"))]
+    date.as_secs().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daap::DaapType;
+    use alloc::vec;
+
+    fn core_item(code: &[u8; 4], data: &[u8]) -> MetadataItem {
+        MetadataItem {
+            item_type: FourCC::CORE,
+            code: FourCC::new(code),
+            data: data.to_vec(),
+        }
+    }
+
+    // A well-formed payload for each wire type
+    fn sample_payload(daap_type: DaapType) -> Vec<u8> {
+        match daap_type {
+            DaapType::String => b"text".to_vec(),
+            // 2001-01-01, after the Unix epoch so it decodes without `std` too
+            DaapType::Date => 3_061_152_000u32.to_be_bytes().to_vec(),
+            _ => (1..=daap_type.width().unwrap() as u8).collect(),
         }
     }
+
+    #[test]
+    fn every_daap_code_has_a_variant_of_its_type() {
+        for &(code, daap_type) in daap::DAAP_CODES {
+            let item = core_item(&code.0, &sample_payload(daap_type));
+            let metadata = ShairportMetadata::from_item(&item);
+            assert!(!matches!(metadata, ShairportMetadata::Other { .. }), "{} decoded as Other", code);
+            assert_eq!(metadata.to_item(), Some(item), "{} doesn't round-trip", code);
+        }
+    }
+
+    #[test]
+    fn daap_codes_are_sorted() {
+        assert!(daap::DAAP_CODES.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn daap_values_of_the_wrong_size_are_kept_as_other() {
+        for (code, data) in [(b"astn", vec![0, 0, 3]), (b"caps", vec![]), (b"mper", vec![1; 4])] {
+            let item = core_item(code, &data);
+            let metadata = ShairportMetadata::from_item(&item);
+            assert!(matches!(metadata, ShairportMetadata::Other { .. }));
+            assert_eq!(metadata.to_item(), Some(item));
+        }
+    }
+
+    #[test]
+    fn codes_decode_to_their_table_meaning() {
+        let decode = |code, data: &[u8]| ShairportMetadata::from_item(&core_item(code, data));
+
+        assert!(matches!(decode(b"caps", &[4]), ShairportMetadata::PlayStatus(4)));
+        assert!(matches!(decode(b"ascp", b"Bach"), ShairportMetadata::Composer(s) if s == "Bach"));
+        assert!(matches!(decode(b"asco", &[1]), ShairportMetadata::Compilation(1)));
+        assert!(matches!(decode(b"asbr", &[1, 64]), ShairportMetadata::Bitrate(320)));
+        assert!(matches!(decode(b"asbt", &[0, 120]), ShairportMetadata::BeatsPerMinute(120)));
+        assert!(matches!(
+            decode(b"asai", &[0, 0, 0, 0, 0, 0, 1, 0]),
+            ShairportMetadata::AlbumId(256)
+        ));
+        assert!(matches!(
+            decode(b"mper", &[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0]),
+            ShairportMetadata::PersistentId(0x1234_5678_9abc_def0)
+        ));
+        assert!(matches!(decode(b"asst", &[0; 4]), ShairportMetadata::StartTime(d) if d.is_zero()));
+        assert!(matches!(decode(b"askd", &[0xb6, 0x75, 0x5e, 0x00]), ShairportMetadata::LastSkipDate(_)));
+        assert!(matches!(decode(b"assn", b"Title, The"), ShairportMetadata::SortTitle(s) if s == "Title, The"));
+    }
+
+    #[test]
+    fn core_codes_outside_the_table_are_other() {
+        let item = core_item(b"zzzz", b"text");
+        assert!(matches!(ShairportMetadata::from_item(&item), ShairportMetadata::Other { .. }));
+    }
 }
@@ -1,54 +1,250 @@
 use crate::error::{MetadataError, Result};
+use crate::fourcc::FourCC;
 use crate::metadata::{MetadataItem, ShairportMetadata};
-use std::convert::TryInto;
-use std::collections::VecDeque;
-use log::debug;
+use crate::ssnc::SampleClock;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::time::Duration;
+use log::{debug, info, warn};
 use base64::{engine::general_purpose, Engine as _};
 
+/// How the parsers deal with malformed items
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    /// Return an error for the first malformed item
+    Strict,
+    /// Log the problem, emit a diagnostic and carry on with the next item
+    #[default]
+    Lenient,
+}
+
+/// Framing of the input stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputFormat {
+    /// Detect from the first bytes of the stream
+    #[default]
+    Auto,
+    /// shairport-sync pipe XML (`<item><type>…`)
+    Xml,
+    /// `<type:4><code:4><length:u32 BE><data>` records
+    Binary,
+}
+
+impl core::str::FromStr for InputFormat {
+    type Err = MetadataError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "xml" => Ok(Self::Xml),
+            "binary" => Ok(Self::Binary),
+            _ => Err(MetadataError::Parse(format!("unknown input format '{}'", s))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParserConfig {
+    pub mode: ParseMode,
+    /// Input framing, only used by `AutoDetectParser`
+    pub format: InputFormat,
+    /// Largest decoded payload accepted for a single item
+    pub max_item_size: usize,
+    /// Largest amount of unparsed input kept while waiting for more data
+    pub max_buffer_size: usize,
+    /// How long to wait for the missing chunks of a UDP item
+    pub chunk_timeout: Duration,
+}
+
+impl ParserConfig {
+    pub const DEFAULT_MAX_ITEM_SIZE: usize = 16 * 1024 * 1024;
+    pub const DEFAULT_MAX_BUFFER_SIZE: usize = 32 * 1024 * 1024;
+    pub const DEFAULT_CHUNK_TIMEOUT: Duration = Duration::from_secs(5);
+}
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        Self {
+            mode: ParseMode::default(),
+            format: InputFormat::default(),
+            max_item_size: Self::DEFAULT_MAX_ITEM_SIZE,
+            max_buffer_size: Self::DEFAULT_MAX_BUFFER_SIZE,
+            chunk_timeout: Self::DEFAULT_CHUNK_TIMEOUT,
+        }
+    }
+}
+
+// Item types shairport-sync writes, used to find the next header after garbage
+const KNOWN_TYPES: [FourCC; 3] = [FourCC::CORE, FourCC::SSNC, FourCC::PICT];
+
+// Result of one parsing step, shared by both parsers
+enum Parsed {
+    Item(MetadataItem),
+    // Bytes were skipped to get back in sync, parsing resumes at `offset`
+    Skipped { bytes: usize, offset: u64 },
+}
+
+impl Parsed {
+    fn into_metadata(self) -> ShairportMetadata {
+        match self {
+            Self::Item(item) => ShairportMetadata::from_item(&item),
+            Self::Skipped { bytes, offset } => ShairportMetadata::Resynchronized { skipped: bytes, offset },
+        }
+    }
+}
+
+// Pull parsing steps until an item comes out. Skip events are dropped, and
+// so are malformed items in lenient mode.
+fn next_item_from(
+    mode: ParseMode,
+    mut next: impl FnMut() -> Result<Option<Parsed>>,
+) -> Result<Option<MetadataItem>> {
+    loop {
+        match next() {
+            Ok(Some(Parsed::Item(item))) => return Ok(Some(item)),
+            Ok(Some(Parsed::Skipped { .. })) => {}
+            Ok(None) => return Ok(None),
+            Err(e) if mode == ParseMode::Lenient && e.is_item_error() => {
+                warn!("Skipping malformed item: {}", e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Single parsing step as metadata. In lenient mode malformed items become
+// `ShairportMetadata::Diagnostic` events.
+fn next_metadata_from(mode: ParseMode, next: Result<Option<Parsed>>) -> Result<Option<ShairportMetadata>> {
+    match next {
+        Ok(parsed) => Ok(parsed.map(Parsed::into_metadata)),
+        Err(e) if mode == ParseMode::Lenient && e.is_item_error() => {
+            warn!("Skipping malformed item: {}", e);
+            Ok(ShairportMetadata::from_error(&e))
+        }
+        Err(e) => Err(e),
+    }
+}
+
 pub struct MetadataParser {
+    config: ParserConfig,
     buffer: Vec<u8>,
     position: usize,
+    // Stream offset of buffer[0], i.e. bytes already cleared
+    consumed: u64,
+    // Bytes skipped while looking for a header, not yet reported
+    skipped: usize,
+    // Payload bytes still to be dropped from a rejected item
+    discard: usize,
+    // Whether the current position is trusted to start a header. Only out of
+    // sync are headers checked for plausibility.
+    synced: bool,
+    clock: SampleClock,
 }
 
 impl MetadataParser {
     pub fn new() -> Self {
+        Self::with_config(ParserConfig::default())
+    }
+
+    pub fn with_config(config: ParserConfig) -> Self {
         Self {
+            config,
             buffer: Vec::new(),
             position: 0,
+            consumed: 0,
+            skipped: 0,
+            discard: 0,
+            synced: true,
+            clock: SampleClock::default(),
         }
     }
 
+    // Parser for data detected as binary. Unless it starts with a header the
+    // detection found items after garbage, so begin by resynchronizing.
+    pub(crate) fn detected(config: ParserConfig, data: &[u8]) -> Self {
+        let mut parser = Self::with_config(config);
+        parser.synced = is_plausible_header(data);
+        parser
+    }
+
     pub fn feed_data(&mut self, data: &[u8]) {
         self.buffer.extend_from_slice(data);
         debug!("Fed {} bytes, total buffer size: {}", data.len(), self.buffer.len());
     }
 
+    /// Parse the next complete item. Garbage in front of it is skipped and
+    /// logged; use `parse_next_metadata` to also get the skip as an event.
     pub fn parse_next_item(&mut self) -> Result<Option<MetadataItem>> {
+        let buffer = core::mem::take(&mut self.buffer);
+        let result = next_item_from(self.config.mode, || self.next_parsed(&buffer));
+        self.buffer = buffer;
+        result
+    }
+
+    pub fn parse_next_metadata(&mut self) -> Result<Option<ShairportMetadata>> {
+        let buffer = core::mem::take(&mut self.buffer);
+        let result = self.parse_metadata_in(&buffer);
+        self.buffer = buffer;
+        result
+    }
+
+    pub fn clear_processed(&mut self) {
+        let processed = self.release_processed();
+        self.buffer.drain(0..processed);
+    }
+
+    // Parse from a buffer owned by the caller (the codec), starting at the
+    // current position
+    pub(crate) fn parse_metadata_in(&mut self, buf: &[u8]) -> Result<Option<ShairportMetadata>> {
+        let metadata = next_metadata_from(self.config.mode, self.next_parsed(buf))?;
+        Ok(metadata.map(|metadata| self.clock.apply(metadata)))
+    }
+
+    // Forget the processed bytes, returning how many the caller can drop
+    pub(crate) fn release_processed(&mut self) -> usize {
+        let processed = core::mem::take(&mut self.position);
+        if processed > 0 {
+            debug!("Clearing {} processed bytes from buffer", processed);
+            self.consumed += processed as u64;
+        }
+        processed
+    }
+
+    fn next_parsed(&mut self, buf: &[u8]) -> Result<Option<Parsed>> {
+        if self.discard > 0 {
+            let dropped = self.discard.min(buf.len() - self.position);
+            self.position += dropped;
+            self.discard -= dropped;
+            if self.discard > 0 {
+                return Ok(None);
+            }
+        }
+        if !self.seek_header(buf) {
+            return Ok(None);
+        }
+        if self.skipped > 0 {
+            let bytes = core::mem::take(&mut self.skipped);
+            let offset = self.consumed + self.position as u64;
+            warn!("Resynchronized after skipping {} bytes, resuming at offset {}", bytes, offset);
+            return Ok(Some(Parsed::Skipped { bytes, offset }));
+        }
+
         // Need at least 12 bytes for: item_type(4) + code(4) + length(4)
-        if self.buffer.len() - self.position < 12 {
-            debug!("Not enough bytes for header: need 12, have {}", self.buffer.len() - self.position);
+        if buf.len() - self.position < 12 {
+            debug!("Not enough bytes for header: need 12, have {}", buf.len() - self.position);
             return Ok(None);
         }
 
-        let item_type = String::from_utf8(
-            self.buffer[self.position..self.position + 4].to_vec()
-        ).unwrap_or_else(|_| {
-            format!("0x{:02x}{:02x}{:02x}{:02x}", 
-                self.buffer[self.position], self.buffer[self.position + 1],
-                self.buffer[self.position + 2], self.buffer[self.position + 3])
-        });
+        let offset = self.consumed + self.position as u64;
+
+        let item_type = fourcc_at(buf, self.position)?;
         self.position += 4;
 
-        let code = String::from_utf8(
-            self.buffer[self.position..self.position + 4].to_vec()
-        ).unwrap_or_else(|_| {
-            format!("0x{:02x}{:02x}{:02x}{:02x}", 
-                self.buffer[self.position], self.buffer[self.position + 1],
-                self.buffer[self.position + 2], self.buffer[self.position + 3])
-        });
+        let code = fourcc_at(buf, self.position)?;
         self.position += 4;
 
-        let length_bytes: [u8; 4] = self.buffer[self.position..self.position + 4]
+        let length_bytes: [u8; 4] = buf[self.position..self.position + 4]
             .try_into()
             .map_err(|_| MetadataError::InvalidFormat)?;
         let length = u32::from_be_bytes(length_bytes) as usize;
@@ -56,149 +252,504 @@ impl MetadataParser {
 
         debug!("Parsing item: type='{}', code='{}', length={}", item_type, code, length);
 
+        // Don't trust the payload length of a rejected header, the scan for the
+        // next header skips whatever payload there is
+        if length > self.config.max_item_size {
+            self.synced = false;
+            return Err(MetadataError::ItemTooLarge {
+                item_type,
+                code,
+                offset,
+                length,
+                limit: self.config.max_item_size,
+            });
+        }
+
         // Check if we have enough data for the payload
-        if self.buffer.len() - self.position < length {
-            debug!("Not enough bytes for payload: need {}, have {}", length, self.buffer.len() - self.position);
+        let available = buf.len() - self.position;
+        if available < length {
+            if available > self.config.max_buffer_size {
+                self.discard = length - available;
+                self.position = buf.len();
+                return Err(MetadataError::BufferOverflow {
+                    item_type,
+                    code,
+                    offset,
+                    limit: self.config.max_buffer_size,
+                });
+            }
+            debug!("Not enough bytes for payload: need {}, have {}", length, available);
             // Reset position to try again later
             self.position -= 12;
             return Ok(None);
         }
 
-        let data = self.buffer[self.position..self.position + length].to_vec();
+        let data = buf[self.position..self.position + length].to_vec();
         self.position += length;
 
         debug!("Successfully parsed item with {} bytes of data", data.len());
 
-        Ok(Some(MetadataItem {
+        Ok(Some(Parsed::Item(MetadataItem {
             item_type,
             code,
             data,
-        }))
+        })))
     }
 
-    pub fn parse_next_metadata(&mut self) -> Result<Option<ShairportMetadata>> {
-        if let Some(item) = self.parse_next_item()? {
-            Ok(Some(ShairportMetadata::from_item(&item)))
-        } else {
-            Ok(None)
+    // Out of sync, move to the next plausible item header, skipping anything
+    // before it. Returns false if more data is needed to find one.
+    fn seek_header(&mut self, buf: &[u8]) -> bool {
+        if self.synced {
+            return true;
+        }
+        let available = buf.len() - self.position;
+        if available < 8 {
+            return false;
         }
-    }
 
-    pub fn clear_processed(&mut self) {
-        if self.position > 0 {
-            debug!("Clearing {} processed bytes from buffer", self.position);
-            self.buffer.drain(0..self.position);
-            self.position = 0;
+        let found = (self.position..=buf.len() - 8)
+            .find(|&i| is_plausible_header(&buf[i..]));
+        // Without a match keep the last 7 bytes, they may start a header
+        let next = found.unwrap_or(buf.len() - 7);
+        if next > self.position {
+            debug!("No valid header at offset {}, skipping {} bytes", self.consumed + self.position as u64, next - self.position);
         }
+        self.skipped += next - self.position;
+        self.position = next;
+        self.synced = found.is_some();
+        self.synced
     }
 }
 
+fn fourcc_at(buf: &[u8], position: usize) -> Result<FourCC> {
+    let bytes: [u8; 4] = buf[position..position + 4]
+        .try_into()
+        .map_err(|_| MetadataError::InvalidFormat)?;
+    Ok(FourCC(bytes))
+}
+
+// Where a header is looked for, in detection and while resynchronizing, it
+// must start with a known type followed by an alphanumeric code
+fn is_plausible_header(bytes: &[u8]) -> bool {
+    bytes.len() >= 8
+        && KNOWN_TYPES.iter().any(|t| bytes[..4] == t.0[..])
+        && bytes[4..8].iter().all(|b| b.is_ascii_alphanumeric())
+}
+
 impl Default for MetadataParser {
     fn default() -> Self {
         Self::new()
     }
 }
 
-// XML parser for stdin input (like C version)
+/// Parse a metadata datagram as sent to shairport-sync's `metadata`
+/// `socket_address`: `<type:4><code:4><data>`, without a length field.
+/// Large items arrive as `ssnc:chnk` items, see `ChunkReassembler`.
+pub fn parse_udp_packet(packet: &[u8]) -> Result<MetadataItem> {
+    if packet.len() < 8 {
+        debug!("Datagram too short for type and code: {} bytes", packet.len());
+        return Err(MetadataError::InvalidFormat);
+    }
+
+    Ok(MetadataItem {
+        item_type: fourcc_at(packet, 0)?,
+        code: fourcc_at(packet, 4)?,
+        data: packet[8..].to_vec(),
+    })
+}
+
+// Where the XML parser is within the tag structure between calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum XmlState {
+    // Outside of any item, waiting for <item>
+    Idle,
+    // Inside <item>, waiting for a field tag or </item>
+    Item,
+    // Collecting the text of <type>, <code> or <length>
+    Field(XmlField),
+    // Collecting base64 text until </data>
+    Data,
+    // Dropping the rest of an item already reported as malformed, until
+    // </item> or the next <item>
+    Rejected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum XmlField {
+    Type,
+    Code,
+    Length,
+}
+
+impl XmlField {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Type => "type",
+            Self::Code => "code",
+            Self::Length => "length",
+        }
+    }
+
+    fn closing_tag(&self) -> &'static str {
+        match self {
+            Self::Type => "/type",
+            Self::Code => "/code",
+            Self::Length => "/length",
+        }
+    }
+}
+
+// Fields collected so far for the item currently being parsed
+#[derive(Debug, Default)]
+struct PartialItem {
+    offset: u64,
+    item_type: Option<FourCC>,
+    code: Option<FourCC>,
+    length: Option<usize>,
+    base64: Vec<u8>,
+}
+
+impl PartialItem {
+    // Type and code for error reporting, "????" if not seen yet
+    fn fourccs(&self) -> (FourCC, FourCC) {
+        (
+            self.item_type.unwrap_or(UNKNOWN_FOURCC),
+            self.code.unwrap_or(UNKNOWN_FOURCC),
+        )
+    }
+}
+
+const UNKNOWN_FOURCC: FourCC = FourCC::new(b"????");
+
+// XML parser for the pipe format (like C version). Works on tag boundaries
+// rather than lines, so items may be split across reads in any way.
 pub struct XmlMetadataParser {
-    buffer: String,
-    pending_lines: VecDeque<String>,
+    config: ParserConfig,
+    buffer: Vec<u8>,
+    position: usize,
+    // Stream offset of buffer[0], i.e. bytes already cleared
+    consumed: u64,
+    state: XmlState,
+    item: PartialItem,
+    text: Vec<u8>,
+    // Bytes skipped outside of items, not yet reported
+    skipped: usize,
+    clock: SampleClock,
 }
 
 impl XmlMetadataParser {
     pub fn new() -> Self {
+        Self::with_config(ParserConfig::default())
+    }
+
+    pub fn with_config(config: ParserConfig) -> Self {
         Self {
-            buffer: String::new(),
-            pending_lines: VecDeque::new(),
+            config,
+            buffer: Vec::new(),
+            position: 0,
+            consumed: 0,
+            state: XmlState::Idle,
+            item: PartialItem::default(),
+            text: Vec::new(),
+            skipped: 0,
+            clock: SampleClock::default(),
         }
     }
 
     pub fn feed_data(&mut self, data: &[u8]) {
-        if let Ok(text) = std::str::from_utf8(data) {
-            self.buffer.push_str(text);
-            
-            // Split into lines and add to pending queue
-            while let Some(newline_pos) = self.buffer.find('\n') {
-                let line = self.buffer[..newline_pos].to_string();
-                self.buffer.drain(..=newline_pos);
-                
-                if !line.trim().is_empty() {
-                    self.pending_lines.push_back(line);
-                }
-            }
-        }
+        self.buffer.extend_from_slice(data);
+        debug!("Fed {} bytes, total buffer size: {}", data.len(), self.buffer.len());
+    }
+
+    /// Parse the next complete item. Malformed items are returned as errors
+    /// in strict mode and logged and skipped in lenient mode. Junk between
+    /// items is always skipped.
+    pub fn parse_next_item(&mut self) -> Result<Option<MetadataItem>> {
+        let buffer = core::mem::take(&mut self.buffer);
+        let result = next_item_from(self.config.mode, || self.next_parsed(&buffer));
+        self.buffer = buffer;
+        result
     }
 
+    /// Parse the next item into metadata. In lenient mode a malformed item
+    /// is skipped and reported as `ShairportMetadata::Diagnostic`, skipped
+    /// junk is reported as `ShairportMetadata::Resynchronized`.
     pub fn parse_next_metadata(&mut self) -> Result<Option<ShairportMetadata>> {
-        while let Some(line) = self.pending_lines.pop_front() {
-            
-            // Parse C-style format: <item><type>636f7265</type><code>6d696e6d</code><length>11</length>
-            if let Some(captures) = regex::Regex::new(
-                r"<item><type>([0-9a-fA-F]+)</type><code>([0-9a-fA-F]+)</code><length>(\d+)</length>"
-            ).unwrap().captures(&line) {
-                let type_hex = &captures[1];
-                let code_hex = &captures[2];
-                let length: usize = captures[3].parse().map_err(|_| MetadataError::InvalidFormat)?;
-                
-                // Convert hex strings to 4-character strings
-                let type_num = u32::from_str_radix(type_hex, 16)
-                    .map_err(|_| MetadataError::InvalidFormat)?;
-                let code_num = u32::from_str_radix(code_hex, 16)
-                    .map_err(|_| MetadataError::InvalidFormat)?;
-                
-                let item_type = String::from_utf8(type_num.to_be_bytes().to_vec())
-                    .unwrap_or_else(|_| format!("0x{:08x}", type_num));
-                let code = String::from_utf8(code_num.to_be_bytes().to_vec())
-                    .unwrap_or_else(|_| format!("0x{:08x}", code_num));
-                
-
-                let mut data = Vec::new();
-                
-                if length > 0 {
-                    // We need at least 2 more lines: data start, base64 data+end combined
-                    if self.pending_lines.len() < 2 {
-                        // Put the line back and wait for more data
-                        self.pending_lines.push_front(line);
+        let buffer = core::mem::take(&mut self.buffer);
+        let result = self.parse_metadata_in(&buffer);
+        self.buffer = buffer;
+        result
+    }
+
+    pub fn clear_processed(&mut self) {
+        // Only consumed bytes are dropped; partial tags and items stay buffered
+        let processed = self.release_processed();
+        self.buffer.drain(0..processed);
+    }
+
+    // Parse from a buffer owned by the caller (the codec), starting at the
+    // current position
+    pub(crate) fn parse_metadata_in(&mut self, buf: &[u8]) -> Result<Option<ShairportMetadata>> {
+        let metadata = next_metadata_from(self.config.mode, self.next_parsed(buf))?;
+        Ok(metadata.map(|metadata| self.clock.apply(metadata)))
+    }
+
+    // Forget the processed bytes, returning how many the caller can drop
+    pub(crate) fn release_processed(&mut self) -> usize {
+        let processed = core::mem::take(&mut self.position);
+        if processed > 0 {
+            debug!("Clearing {} processed bytes from buffer", processed);
+            self.consumed += processed as u64;
+        }
+        processed
+    }
+
+    fn next_parsed(&mut self, buf: &[u8]) -> Result<Option<Parsed>> {
+        loop {
+            match self.state {
+                XmlState::Field(_) | XmlState::Data => {
+                    // Everything up to the next '<' is element text
+                    let start = self.position;
+                    let end = find_byte(buf, start, b'<').unwrap_or(buf.len());
+                    if self.state == XmlState::Data {
+                        // Base64 may be wrapped over several lines
+                        self.item.base64.extend(
+                            buf[start..end].iter().filter(|b| !b.is_ascii_whitespace()),
+                        );
+                    } else {
+                        self.text.extend_from_slice(&buf[start..end]);
+                    }
+                    self.position = end;
+                    self.check_limits()?;
+                    if end == buf.len() {
+                        return Ok(None);
+                    }
+                }
+                XmlState::Idle | XmlState::Item | XmlState::Rejected => {
+                    while self.position < buf.len()
+                        && buf[self.position].is_ascii_whitespace()
+                    {
+                        self.position += 1;
+                    }
+                    if self.position == buf.len() {
                         return Ok(None);
                     }
-                    
-                    // Look for data start tag
-                    if let Some(data_start_line) = self.pending_lines.pop_front() {
-                        if data_start_line.trim() == r#"<data encoding="base64">"# {
-                            // Get the base64 data line (which includes </data></item>)
-                            if let Some(base64_line) = self.pending_lines.pop_front() {
-                                // Extract base64 data from line like "U2F5IFlvdSwgU2F5IE1lIOKAlCBPY2VhbnNpZGUvU2VhbGlmZS9CZWFjaGxpZmU=</data></item>"
-                                if let Some(end_pos) = base64_line.find("</data>") {
-                                    let base64_data = &base64_line[..end_pos];
-                                    
-                                    // Decode base64
-                                    if let Ok(decoded) = general_purpose::STANDARD.decode(base64_data.trim()) {
-                                        data = decoded;
-                                    }
-                                }
-                            }
+                    if buf[self.position] != b'<' {
+                        let end = find_byte(buf, self.position, b'<')
+                            .unwrap_or(buf.len());
+                        debug!("Skipping {} bytes of stray text", end - self.position);
+                        if self.state == XmlState::Idle {
+                            self.skipped += end - self.position;
                         }
+                        self.position = end;
+                        continue;
                     }
                 }
+            }
+
+            // We are at '<', wait until the whole tag is buffered
+            let Some(tag_end) = find_byte(buf, self.position, b'>') else {
+                if buf.len() - self.position > self.config.max_buffer_size {
+                    let (item_type, code) = self.item.fourccs();
+                    let offset = self.consumed + self.position as u64;
+                    self.position = buf.len();
+                    self.state = XmlState::Rejected;
+                    return Err(MetadataError::BufferOverflow {
+                        item_type,
+                        code,
+                        offset,
+                        limit: self.config.max_buffer_size,
+                    });
+                }
+                return Ok(None);
+            };
+            let tag_offset = self.consumed + self.position as u64;
+            let tag = String::from_utf8_lossy(&buf[self.position + 1..tag_end])
+                .trim()
+                .to_string();
+            self.position = tag_end + 1;
 
-                let item = MetadataItem {
-                    item_type: item_type.clone(),
-                    code: code.clone(),
-                    data: data.clone(),
+            if let Some(parsed) = self.handle_tag(&tag, tag_offset)? {
+                return Ok(Some(parsed));
+            }
+        }
+    }
+
+    fn handle_tag(&mut self, tag: &str, offset: u64) -> Result<Option<Parsed>> {
+        match (self.state, tag) {
+            (state, "item") => {
+                if !matches!(state, XmlState::Idle | XmlState::Rejected) {
+                    debug!("Discarding incomplete item, new <item> started");
+                    self.skipped += (offset - self.item.offset) as usize;
+                }
+                self.item = PartialItem {
+                    offset,
+                    ..PartialItem::default()
                 };
-                
-                return Ok(Some(ShairportMetadata::from_item(&item)));
+                self.state = XmlState::Item;
+                if self.skipped > 0 {
+                    let bytes = core::mem::take(&mut self.skipped);
+                    warn!("Resynchronized after skipping {} bytes, resuming at offset {}", bytes, offset);
+                    return Ok(Some(Parsed::Skipped { bytes, offset }));
+                }
+            }
+            (XmlState::Item, "type") => self.start_field(XmlField::Type),
+            (XmlState::Item, "code") => self.start_field(XmlField::Code),
+            (XmlState::Item, "length") => self.start_field(XmlField::Length),
+            (XmlState::Item, t) if t == "data" || t.starts_with("data ") => {
+                self.item.base64.clear();
+                self.state = XmlState::Data;
+            }
+            (XmlState::Field(field), t) if t == field.closing_tag() => {
+                self.state = XmlState::Item;
+                self.finish_field(field)?;
+            }
+            (XmlState::Data, "/data") => self.state = XmlState::Item,
+            (XmlState::Item, "/item") => {
+                self.state = XmlState::Idle;
+                return self.finish_item().map(|item| Some(Parsed::Item(item)));
+            }
+            (XmlState::Rejected, "/item") => self.state = XmlState::Idle,
+            (XmlState::Rejected, _) => {}
+            (XmlState::Idle, t) => {
+                debug!("Ignoring tag <{}> outside of an item", t);
+                self.skipped += t.len() + 2;
+            }
+            (_, t) => {
+                // Drop the rest of this item, the next <item> starts over
+                let (item_type, code) = self.item.fourccs();
+                self.state = XmlState::Rejected;
+                return Err(MetadataError::UnexpectedTag {
+                    tag: t.to_string(),
+                    item_type,
+                    code,
+                    offset,
+                });
             }
         }
-        
         Ok(None)
     }
 
-    pub fn clear_processed(&mut self) {
-        // Keep some buffer for incomplete lines
-        if self.buffer.len() > 10000 {
-            self.buffer.clear();
+    // Drop the current item once its field text or base64 grows past the limits
+    fn check_limits(&mut self) -> Result<()> {
+        let max_base64 = self.config.max_item_size.div_ceil(3) * 4;
+        if self.item.base64.len() > max_base64 {
+            let (item_type, code) = self.item.fourccs();
+            let length = self.item.length.unwrap_or(self.item.base64.len() / 4 * 3);
+            self.item.base64 = Vec::new();
+            self.state = XmlState::Rejected;
+            return Err(MetadataError::ItemTooLarge {
+                item_type,
+                code,
+                offset: self.item.offset,
+                length,
+                limit: self.config.max_item_size,
+            });
         }
+        if self.text.len() > self.config.max_buffer_size {
+            let (item_type, code) = self.item.fourccs();
+            self.text = Vec::new();
+            self.state = XmlState::Rejected;
+            return Err(MetadataError::BufferOverflow {
+                item_type,
+                code,
+                offset: self.item.offset,
+                limit: self.config.max_buffer_size,
+            });
+        }
+        Ok(())
+    }
+
+    fn start_field(&mut self, field: XmlField) {
+        self.text.clear();
+        self.state = XmlState::Field(field);
+    }
+
+    fn finish_field(&mut self, field: XmlField) -> Result<()> {
+        let text = String::from_utf8_lossy(&self.text).trim().to_string();
+        let parsed = match field {
+            XmlField::Type => decode_hex_fourcc(&text).map(|t| self.item.item_type = Some(t)),
+            XmlField::Code => decode_hex_fourcc(&text).map(|c| self.item.code = Some(c)),
+            XmlField::Length => text.parse().map(|l| self.item.length = Some(l)).ok(),
+        };
+        if parsed.is_none() {
+            let (item_type, code) = self.item.fourccs();
+            self.state = XmlState::Rejected;
+            return Err(MetadataError::InvalidField {
+                field: field.name(),
+                value: text,
+                item_type,
+                code,
+                offset: self.item.offset,
+            });
+        }
+
+        // Reject oversized items before buffering their payload
+        if let Some(length) = self.item.length
+            && length > self.config.max_item_size
+        {
+            let (item_type, code) = self.item.fourccs();
+            self.state = XmlState::Rejected;
+            return Err(MetadataError::ItemTooLarge {
+                item_type,
+                code,
+                offset: self.item.offset,
+                length,
+                limit: self.config.max_item_size,
+            });
+        }
+        Ok(())
+    }
+
+    fn finish_item(&mut self) -> Result<MetadataItem> {
+        let item = core::mem::take(&mut self.item);
+        let (Some(item_type), Some(code)) = (item.item_type, item.code) else {
+            let (item_type, code) = item.fourccs();
+            let field = if item.item_type.is_none() { XmlField::Type } else { XmlField::Code };
+            return Err(MetadataError::MissingField {
+                field: field.name(),
+                item_type,
+                code,
+                offset: item.offset,
+            });
+        };
+
+        let data = if item.base64.is_empty() {
+            Vec::new()
+        } else {
+            match general_purpose::STANDARD.decode(&item.base64) {
+                Ok(data) => data,
+                Err(e) => {
+                    return Err(MetadataError::InvalidBase64 {
+                        item_type,
+                        code,
+                        offset: item.offset,
+                        message: e.to_string(),
+                    });
+                }
+            }
+        };
+
+        // A missing <data> block shows up here as a zero-length payload
+        if let Some(length) = item.length
+            && length != data.len()
+        {
+            return Err(MetadataError::LengthMismatch {
+                item_type,
+                code,
+                offset: item.offset,
+                expected: length,
+                actual: data.len(),
+            });
+        }
+
+        debug!("Successfully parsed XML item {}:{} with {} bytes of data", item_type, code, data.len());
+
+        Ok(MetadataItem {
+            item_type,
+            code,
+            data,
+        })
     }
 }
 
@@ -206,4 +757,750 @@ impl Default for XmlMetadataParser {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+fn find_byte(buffer: &[u8], from: usize, byte: u8) -> Option<usize> {
+    buffer[from..].iter().position(|&b| b == byte).map(|i| from + i)
+}
+
+// Convert a hex type/code like "636f7265" to its four bytes ("core")
+fn decode_hex_fourcc(hex: &str) -> Option<FourCC> {
+    u32::from_str_radix(hex, 16).ok().map(FourCC::from_u32)
+}
+
+/// Parse a complete buffer of pipe XML
+pub fn parse_xml_bytes(data: &[u8]) -> Result<Vec<ShairportMetadata>> {
+    parse_xml_bytes_with_config(data, ParserConfig::default())
+}
+
+pub fn parse_xml_bytes_with_config(data: &[u8], config: ParserConfig) -> Result<Vec<ShairportMetadata>> {
+    let mut parser = XmlMetadataParser::with_config(config);
+    parser.feed_data(data);
+    let mut metadata_items = Vec::new();
+    while let Some(metadata) = parser.parse_next_metadata()? {
+        metadata_items.push(metadata);
+    }
+    Ok(metadata_items)
+}
+
+/// Parse a complete buffer of binary framed items
+pub fn parse_binary_bytes(data: &[u8]) -> Result<Vec<ShairportMetadata>> {
+    parse_binary_bytes_with_config(data, ParserConfig::default())
+}
+
+pub fn parse_binary_bytes_with_config(data: &[u8], config: ParserConfig) -> Result<Vec<ShairportMetadata>> {
+    parse_binary_with(MetadataParser::with_config(config), data)
+}
+
+fn parse_binary_with(mut parser: MetadataParser, data: &[u8]) -> Result<Vec<ShairportMetadata>> {
+    parser.feed_data(data);
+    let mut metadata_items = Vec::new();
+    while let Some(metadata) = parser.parse_next_metadata()? {
+        metadata_items.push(metadata);
+    }
+    Ok(metadata_items)
+}
+
+/// Parse a complete buffer in either format, detected from its contents
+/// unless set in the config. Undetectable input is treated as XML.
+pub fn parse_bytes(data: &[u8]) -> Result<Vec<ShairportMetadata>> {
+    parse_bytes_with_config(data, ParserConfig::default())
+}
+
+pub fn parse_bytes_with_config(data: &[u8], config: ParserConfig) -> Result<Vec<ShairportMetadata>> {
+    match config.format {
+        InputFormat::Auto => match detect_format(data, config.max_item_size, true) {
+            Some(InputFormat::Binary) => parse_binary_with(MetadataParser::detected(config, data), data),
+            _ => parse_xml_bytes_with_config(data, config),
+        },
+        InputFormat::Binary => parse_binary_bytes_with_config(data, config),
+        InputFormat::Xml => parse_xml_bytes_with_config(data, config),
+    }
+}
+
+// Give up on detection and assume XML after this many bytes without a match
+const DETECT_LIMIT: usize = 4096;
+
+enum DetectState {
+    Pending(Vec<u8>, FormatDetector),
+    Xml(XmlMetadataParser),
+    Binary(MetadataParser),
+}
+
+/// Front end that picks the XML or binary parser from the first bytes of
+/// the stream, or uses the format forced in `ParserConfig::format`.
+pub struct AutoDetectParser {
+    config: ParserConfig,
+    state: DetectState,
+}
+
+impl AutoDetectParser {
+    pub fn new() -> Self {
+        Self::with_config(ParserConfig::default())
+    }
+
+    pub fn with_config(config: ParserConfig) -> Self {
+        let state = match config.format {
+            InputFormat::Auto => DetectState::Pending(Vec::new(), FormatDetector::with_config(&config)),
+            InputFormat::Xml => DetectState::Xml(XmlMetadataParser::with_config(config.clone())),
+            InputFormat::Binary => DetectState::Binary(MetadataParser::with_config(config.clone())),
+        };
+        Self { config, state }
+    }
+
+    /// The format in use, `None` while still detecting
+    pub fn format(&self) -> Option<InputFormat> {
+        match self.state {
+            DetectState::Pending(..) => None,
+            DetectState::Xml(_) => Some(InputFormat::Xml),
+            DetectState::Binary(_) => Some(InputFormat::Binary),
+        }
+    }
+
+    pub fn feed_data(&mut self, data: &[u8]) {
+        match &mut self.state {
+            DetectState::Pending(pending, detector) => {
+                pending.extend_from_slice(data);
+                if let Some(format) = detector.detect(pending, false) {
+                    self.start(format);
+                }
+            }
+            DetectState::Xml(parser) => parser.feed_data(data),
+            DetectState::Binary(parser) => parser.feed_data(data),
+        }
+    }
+
+    /// Call at end of input: data too short to detect the format of while
+    /// streaming is detected as a whole, as `parse_bytes` does, so that the
+    /// items in it can still be parsed
+    pub fn finish(&mut self) {
+        if let DetectState::Pending(pending, detector) = &mut self.state {
+            let format = detector.detect(pending, true).unwrap_or(InputFormat::Xml);
+            self.start(format);
+        }
+    }
+
+    // Hand the pending data to the parser for the detected format
+    fn start(&mut self, format: InputFormat) {
+        let DetectState::Pending(pending, _) = &mut self.state else {
+            return;
+        };
+        info!("Detected {:?} metadata format", format);
+        let pending = core::mem::take(pending);
+        self.state = match format {
+            InputFormat::Binary => {
+                let mut parser = MetadataParser::detected(self.config.clone(), &pending);
+                parser.feed_data(&pending);
+                DetectState::Binary(parser)
+            }
+            _ => {
+                let mut parser = XmlMetadataParser::with_config(self.config.clone());
+                parser.feed_data(&pending);
+                DetectState::Xml(parser)
+            }
+        };
+    }
+
+    pub fn parse_next_item(&mut self) -> Result<Option<MetadataItem>> {
+        match &mut self.state {
+            DetectState::Pending(..) => Ok(None),
+            DetectState::Xml(parser) => parser.parse_next_item(),
+            DetectState::Binary(parser) => parser.parse_next_item(),
+        }
+    }
+
+    pub fn parse_next_metadata(&mut self) -> Result<Option<ShairportMetadata>> {
+        match &mut self.state {
+            DetectState::Pending(..) => Ok(None),
+            DetectState::Xml(parser) => parser.parse_next_metadata(),
+            DetectState::Binary(parser) => parser.parse_next_metadata(),
+        }
+    }
+
+    pub fn clear_processed(&mut self) {
+        match &mut self.state {
+            DetectState::Pending(..) => {}
+            DetectState::Xml(parser) => parser.clear_processed(),
+            DetectState::Binary(parser) => parser.clear_processed(),
+        }
+    }
+}
+
+impl Default for AutoDetectParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Tags only pipe XML contains
+const XML_MARKERS: [&[u8]; 4] = [b"<item>", b"</item>", b"</data>", b"<type>"];
+const LONGEST_MARKER: usize = 7;
+
+// Any XML tag means XML. A binary header only counts at the very start, or
+// when its length leads straight to a second header: the base64 tail in front
+// of the first <item> of a stream joined midway can look like a lone header.
+// With `complete` set, a header whose item ends with the data also counts
+// and undetectable data gives `None` right away.
+pub(crate) fn detect_format(data: &[u8], max_item_size: usize, complete: bool) -> Option<InputFormat> {
+    FormatDetector::new(max_item_size, usize::MAX).detect(data, complete)
+}
+
+// Detection over a growing buffer, carrying on where the previous call
+// stopped. Pending data is capped at DETECT_LIMIT, or `max_buffer_size` while
+// a possible header waits for the rest of its item.
+pub(crate) struct FormatDetector {
+    max_item_size: usize,
+    max_buffer_size: usize,
+    // Bytes already searched for XML markers
+    xml_checked: usize,
+    // Next offset to check for a header
+    header_checked: usize,
+    // Headers whose item hasn't been checked against the data yet
+    candidates: Vec<usize>,
+}
+
+impl FormatDetector {
+    pub(crate) fn new(max_item_size: usize, max_buffer_size: usize) -> Self {
+        Self {
+            max_item_size,
+            max_buffer_size,
+            xml_checked: 0,
+            header_checked: 1,
+            candidates: Vec::new(),
+        }
+    }
+
+    pub(crate) fn with_config(config: &ParserConfig) -> Self {
+        Self::new(config.max_item_size, config.max_buffer_size)
+    }
+
+    // `data` must start with the bytes of the previous call
+    pub(crate) fn detect(&mut self, data: &[u8], complete: bool) -> Option<InputFormat> {
+        // Overlap the last search in case a marker was split
+        let from = self.xml_checked.saturating_sub(LONGEST_MARKER - 1);
+        let tail = &data[from.min(data.len())..];
+        self.xml_checked = data.len();
+        if XML_MARKERS.iter().any(|marker| tail.windows(marker.len()).any(|w| w == *marker)) {
+            return Some(InputFormat::Xml);
+        }
+        if is_plausible_header(data) {
+            return Some(InputFormat::Binary);
+        }
+
+        let last_start = data.len().saturating_sub(8);
+        if self.header_checked <= last_start {
+            let new = (self.header_checked..=last_start).filter(|&i| is_plausible_header(&data[i..]));
+            self.candidates.extend(new);
+            self.header_checked = last_start + 1;
+        }
+
+        let mut detected = false;
+        self.candidates.retain(|&start| {
+            match chained_header(data, start, self.max_item_size, complete) {
+                Some(true) => detected = true,
+                Some(false) => return false,
+                None => {}
+            }
+            true
+        });
+        if detected {
+            return Some(InputFormat::Binary);
+        }
+
+        let limit = if self.candidates.is_empty() { DETECT_LIMIT } else { self.max_buffer_size.max(DETECT_LIMIT) };
+        if !complete && data.len() > limit {
+            warn!("Could not detect metadata format after {} bytes, assuming XML", data.len());
+            return Some(InputFormat::Xml);
+        }
+        None
+    }
+}
+
+// Whether the item of the header at `start` is followed by another header,
+// `None` while that needs more data
+fn chained_header(data: &[u8], start: usize, max_item_size: usize, complete: bool) -> Option<bool> {
+    let length = data.get(start + 8..start + 12)?;
+    let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+    if length > max_item_size {
+        return Some(false);
+    }
+    let next = start + 12 + length;
+    if data.len() >= next + 8 {
+        Some(is_plausible_header(&data[next..]))
+    } else if complete {
+        Some(next == data.len())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const MULTILINE: &[u8] = include_bytes!("../tests/data/multiline_layout.xml");
+    const COMPACT: &[u8] = include_bytes!("../test_input.xml");
+
+    fn item(item_type: &[u8; 4], code: &[u8; 4], data: &[u8]) -> MetadataItem {
+        MetadataItem {
+            item_type: FourCC::new(item_type),
+            code: FourCC::new(code),
+            data: data.to_vec(),
+        }
+    }
+
+    // The items in both fixtures
+    fn fixture_items() -> Vec<MetadataItem> {
+        vec![
+            item(b"core", b"minm", b"Hello World"),
+            item(b"core", b"asar", b"Test Artist"),
+            item(b"ssnc", b"pbeg", b""),
+            item(b"ssnc", b"pend", b""),
+        ]
+    }
+
+    fn drain(parser: &mut XmlMetadataParser, items: &mut Vec<MetadataItem>) {
+        while let Some(item) = parser.parse_next_item().unwrap() {
+            items.push(item);
+        }
+        parser.clear_processed();
+    }
+
+    fn parse_in_chunks(input: &[u8], chunk_size: usize) -> Vec<MetadataItem> {
+        let mut parser = XmlMetadataParser::new();
+        let mut items = Vec::new();
+        for chunk in input.chunks(chunk_size) {
+            parser.feed_data(chunk);
+            drain(&mut parser, &mut items);
+        }
+        items
+    }
+
+    #[test]
+    fn xml_split_at_every_byte_boundary() {
+        for input in [COMPACT, MULTILINE] {
+            for split in 0..=input.len() {
+                let mut parser = XmlMetadataParser::new();
+                let mut items = Vec::new();
+                parser.feed_data(&input[..split]);
+                drain(&mut parser, &mut items);
+                parser.feed_data(&input[split..]);
+                drain(&mut parser, &mut items);
+                assert_eq!(items, fixture_items(), "split at {}", split);
+            }
+            assert_eq!(parse_in_chunks(input, 1), fixture_items());
+        }
+    }
+
+    #[test]
+    fn xml_base64_wrapped_over_lines() {
+        let input = b"<item><type>636f7265</type><code>6d696e6d</code><length>11</length>\n\
+            <data encoding=\"base64\">\nSGVs\nbG8g\r\nV29y\n  bGQ=\n</data></item>\n";
+        assert_eq!(parse_in_chunks(input, 5), vec![item(b"core", b"minm", b"Hello World")]);
+    }
+
+    #[test]
+    fn xml_compact_single_line_items() {
+        let input = b"<item><type>636f7265</type><code>6d696e6d</code><length>2</length>\
+            <data encoding=\"base64\">aGk=</data></item><item><type>73736e63</type>\
+            <code>70626567</code><length>0</length></item>";
+        assert_eq!(
+            parse_in_chunks(input, input.len()),
+            vec![item(b"core", b"minm", b"hi"), item(b"ssnc", b"pbeg", b"")]
+        );
+        assert_eq!(parse_in_chunks(COMPACT, COMPACT.len()), fixture_items());
+    }
+
+    #[test]
+    fn xml_original_multiline_layout() {
+        assert_eq!(parse_in_chunks(MULTILINE, MULTILINE.len()), fixture_items());
+    }
+
+    #[test]
+    fn xml_item_restarted_before_close() {
+        let truncated = b"<item><type>636f7265</type><code>6d696e6d</code><length>11</length>\n\
+            <data encoding=\"base64\">\nSGVsbG8";
+        let mut input = truncated.to_vec();
+        input.extend_from_slice(MULTILINE);
+
+        assert_eq!(parse_in_chunks(&input, 7), fixture_items());
+
+        let mut parser = XmlMetadataParser::new();
+        parser.feed_data(&input);
+        match parser.parse_next_metadata().unwrap() {
+            Some(ShairportMetadata::Resynchronized { skipped, offset }) => {
+                assert_eq!(skipped, truncated.len());
+                assert_eq!(offset, truncated.len() as u64);
+            }
+            other => panic!("expected a resync, got {:?}", other),
+        }
+    }
+
+    const VALID_ITEM: &[u8] = b"<item><type>636f7265</type><code>6d696e6d</code><length>2</length>\n\
+        <data encoding=\"base64\">\naGk=</data></item>\n";
+
+    fn with_valid_item(bad: &[u8]) -> Vec<u8> {
+        let mut input = bad.to_vec();
+        input.extend_from_slice(VALID_ITEM);
+        input
+    }
+
+    fn strict() -> ParserConfig {
+        ParserConfig {
+            mode: ParseMode::Strict,
+            ..ParserConfig::default()
+        }
+    }
+
+    #[test]
+    fn xml_invalid_field_skips_only_that_item() {
+        let input = with_valid_item(b"<item><type>zz</type><code>6d696e6d</code><length>0</length></item>\n");
+
+        let metadata = parse_xml_bytes(&input).unwrap();
+        assert!(matches!(
+            &metadata[0],
+            ShairportMetadata::Diagnostic { item_type, offset: 0, .. } if *item_type == UNKNOWN_FOURCC
+        ));
+        assert!(matches!(metadata.last(), Some(ShairportMetadata::Title(title)) if title == "hi"));
+
+        match parse_xml_bytes_with_config(&input, strict()) {
+            Err(MetadataError::InvalidField { field: "type", value, .. }) => assert_eq!(value, "zz"),
+            other => panic!("expected an invalid <type>, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn xml_invalid_length_is_an_item_error() {
+        let input = with_valid_item(b"<item><type>636f7265</type><code>6d696e6d</code><length>x</length></item>");
+        let error = parse_xml_bytes_with_config(&input, strict()).unwrap_err();
+        assert!(error.is_item_error());
+        assert!(matches!(
+            error,
+            MetadataError::InvalidField { field: "length", item_type: FourCC::CORE, .. }
+        ));
+        assert!(parse_xml_bytes(&input).unwrap().iter().any(|m| matches!(m, ShairportMetadata::Title(_))));
+    }
+
+    #[test]
+    fn xml_missing_code_is_an_item_error() {
+        let input = with_valid_item(b"<item><type>636f7265</type><length>0</length></item>\n");
+
+        let metadata = parse_xml_bytes(&input).unwrap();
+        assert!(matches!(&metadata[0], ShairportMetadata::Diagnostic { code, .. } if *code == UNKNOWN_FOURCC));
+        assert!(matches!(&metadata[1], ShairportMetadata::Title(title) if title == "hi"));
+
+        assert!(matches!(
+            parse_xml_bytes_with_config(&input, strict()),
+            Err(MetadataError::MissingField { field: "code", item_type: FourCC::CORE, offset: 0, .. })
+        ));
+    }
+
+    fn binary(items: &[MetadataItem]) -> Vec<u8> {
+        items
+            .iter()
+            .flat_map(|item| crate::encoder::encode_binary_item(item).unwrap())
+            .collect()
+    }
+
+    // All metadata from feeding `input` in chunks of `chunk_size`
+    fn binary_metadata(input: &[u8], config: ParserConfig, chunk_size: usize) -> Vec<ShairportMetadata> {
+        let mut parser = MetadataParser::with_config(config);
+        let mut metadata = Vec::new();
+        for chunk in input.chunks(chunk_size) {
+            parser.feed_data(chunk);
+            while let Some(m) = parser.parse_next_metadata().unwrap() {
+                metadata.push(m);
+            }
+            parser.clear_processed();
+        }
+        metadata
+    }
+
+    // Skipped byte counts and resume offsets of the resync events
+    fn resyncs(metadata: &[ShairportMetadata]) -> Vec<(usize, u64)> {
+        metadata
+            .iter()
+            .filter_map(|m| match m {
+                ShairportMetadata::Resynchronized { skipped, offset } => Some((*skipped, *offset)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn titles(metadata: &[ShairportMetadata]) -> Vec<&str> {
+        metadata
+            .iter()
+            .filter_map(|m| match m {
+                ShairportMetadata::Title(title) => Some(title.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn binary_resync_after_leading_garbage() {
+        let mut input = b"\x00\xffjunk".to_vec();
+        input.extend(binary(&[item(b"core", b"minm", b"one"), item(b"core", b"minm", b"two")]));
+
+        for chunk_size in [1, 3, input.len()] {
+            let mut parser = AutoDetectParser::new();
+            let mut metadata = Vec::new();
+            for chunk in input.chunks(chunk_size) {
+                parser.feed_data(chunk);
+                while let Some(m) = parser.parse_next_metadata().unwrap() {
+                    metadata.push(m);
+                }
+                parser.clear_processed();
+            }
+            assert_eq!(resyncs(&metadata), vec![(6, 6)], "chunks of {}", chunk_size);
+            assert_eq!(titles(&metadata), vec!["one", "two"]);
+        }
+
+        let metadata = parse_bytes(&input).unwrap();
+        assert_eq!(resyncs(&metadata), vec![(6, 6)]);
+        assert_eq!(titles(&metadata), vec!["one", "two"]);
+    }
+
+    #[test]
+    fn binary_in_sync_trusts_any_header() {
+        let items = [
+            item(b"core", b"minm", b"one"),
+            item(b"dmap", b"abcd", b"\x00\x01"),
+            item(b"ssnc", b"PIC ", b""),
+            item(b"\xff\x00\x10\x7f", b"\x00\x00\x00\x00", b"raw"),
+            item(b"core", b"minm", b"two"),
+        ];
+        let input = binary(&items);
+
+        for chunk_size in [1, 5, input.len()] {
+            let mut parser = MetadataParser::new();
+            let mut parsed = Vec::new();
+            for chunk in input.chunks(chunk_size) {
+                parser.feed_data(chunk);
+                while let Some(item) = parser.parse_next_item().unwrap() {
+                    parsed.push(item);
+                }
+                parser.clear_processed();
+            }
+            assert_eq!(parsed, items, "chunks of {}", chunk_size);
+        }
+        assert!(resyncs(&parse_bytes(&input).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn binary_resync_after_truncated_item() {
+        let first = binary(&[item(b"core", b"minm", b"one")]);
+        let second = binary(&[item(b"core", b"minm", b"two")]);
+        // The header of a lost item followed by a few bytes of its payload,
+        // with a length too large to trust
+        let mut input = first.clone();
+        input.extend_from_slice(b"ssncpvol\xff\xff\xff\xff-2");
+        input.extend_from_slice(&second);
+
+        let metadata = binary_metadata(&input, ParserConfig::default(), input.len());
+        assert!(matches!(
+            &metadata[1],
+            ShairportMetadata::Diagnostic { offset, .. } if *offset == first.len() as u64
+        ));
+        assert_eq!(resyncs(&metadata), vec![(2, first.len() as u64 + 14)]);
+        assert_eq!(titles(&metadata), vec!["one", "two"]);
+    }
+
+    #[test]
+    fn xml_resync_counts_junk_between_items() {
+        let mut input = VALID_ITEM.to_vec();
+        input.extend_from_slice(b"garbage<junk>");
+        input.extend_from_slice(VALID_ITEM);
+
+        let mut parser = XmlMetadataParser::new();
+        let mut metadata = Vec::new();
+        for chunk in input.chunks(4) {
+            parser.feed_data(chunk);
+            while let Some(m) = parser.parse_next_metadata().unwrap() {
+                metadata.push(m);
+            }
+            parser.clear_processed();
+        }
+        assert_eq!(resyncs(&metadata), vec![(13, VALID_ITEM.len() as u64 + 13)]);
+        assert_eq!(titles(&metadata), vec!["hi", "hi"]);
+    }
+
+    fn limits(max_item_size: usize, max_buffer_size: usize) -> ParserConfig {
+        ParserConfig {
+            max_item_size,
+            max_buffer_size,
+            ..ParserConfig::default()
+        }
+    }
+
+    fn xml_metadata(input: &[u8], config: ParserConfig, chunk_size: usize) -> Vec<ShairportMetadata> {
+        let mut parser = XmlMetadataParser::with_config(config);
+        let mut metadata = Vec::new();
+        for chunk in input.chunks(chunk_size) {
+            parser.feed_data(chunk);
+            while let Some(m) = parser.parse_next_metadata().unwrap() {
+                metadata.push(m);
+            }
+            parser.clear_processed();
+        }
+        metadata
+    }
+
+    // A diagnostic for the rejected item and the valid one after it, without
+    // the rest of the rejected item showing up as skipped junk
+    fn assert_rejected_then_valid(metadata: &[ShairportMetadata], message: &str) {
+        assert_eq!(metadata.len(), 2, "{:?}", metadata);
+        match &metadata[0] {
+            ShairportMetadata::Diagnostic { message: m, offset: 0, .. } => assert!(m.contains(message), "{}", m),
+            other => panic!("expected a diagnostic, got {:?}", other),
+        }
+        assert!(matches!(&metadata[1], ShairportMetadata::Title(title) if title == "hi"));
+    }
+
+    #[test]
+    fn xml_oversize_length_skips_the_item() {
+        let input = with_valid_item(
+            b"<item><type>636f7265</type><code>6d696e6d</code><length>11</length>\n\
+            <data encoding=\"base64\">\nSGVsbG8gV29ybGQ=</data></item>\n",
+        );
+        for chunk_size in [1, 5, input.len()] {
+            assert_rejected_then_valid(&xml_metadata(&input, limits(4, 64), chunk_size), "too large");
+        }
+    }
+
+    #[test]
+    fn xml_oversize_base64_skips_the_item() {
+        // No <length>, the base64 text itself goes over the limit
+        let input = with_valid_item(
+            b"<item><type>636f7265</type><code>6d696e6d</code>\n\
+            <data encoding=\"base64\">\nSGVsbG8gV29ybGQ=</data></item>\n",
+        );
+        for chunk_size in [1, 5, input.len()] {
+            assert_rejected_then_valid(&xml_metadata(&input, limits(3, 64), chunk_size), "too large");
+        }
+    }
+
+    #[test]
+    fn xml_overlong_field_skips_the_item() {
+        let input = with_valid_item(
+            b"<item><type>636f7265000000000000000000000000000000</type><code>6d696e6d</code></item>\n",
+        );
+        for chunk_size in [1, 5, input.len()] {
+            assert_rejected_then_valid(&xml_metadata(&input, limits(64, 32), chunk_size), "Buffer limit");
+        }
+    }
+
+    #[test]
+    fn binary_oversize_item_resyncs_past_its_payload() {
+        // The length of a rejected header isn't trusted, so its payload is
+        // scanned past as junk
+        let mut input = binary(&[item(b"core", b"minm", b"twenty bytes of text")]);
+        input.extend(binary(&[item(b"core", b"minm", b"hi")]));
+
+        let metadata = binary_metadata(&input, limits(8, 64), input.len());
+        assert!(matches!(&metadata[0], ShairportMetadata::Diagnostic { offset: 0, .. }));
+        assert_eq!(resyncs(&metadata), vec![(20, 32)]);
+        assert_eq!(titles(&metadata), vec!["hi"]);
+    }
+
+    #[test]
+    fn binary_overflow_discards_the_payload() {
+        let payload = [b'x'; 64];
+        let mut input = binary(&[item(b"core", b"minm", &payload)]);
+        input.extend(binary(&[item(b"core", b"minm", b"hi")]));
+
+        for chunk_size in [1, 8, 20] {
+            let metadata = binary_metadata(&input, limits(1024, 16), chunk_size);
+            assert_rejected_then_valid(&metadata, "Buffer limit");
+        }
+    }
+
+    fn detect_in_chunks(input: &[u8], chunk_size: usize) -> Option<InputFormat> {
+        let mut parser = AutoDetectParser::new();
+        for chunk in input.chunks(chunk_size) {
+            parser.feed_data(chunk);
+        }
+        parser.format()
+    }
+
+    #[test]
+    fn detect_xml_joined_mid_base64() {
+        let mut input = b"QUJDcoreABCDxyz</data></item>\n".to_vec();
+        input.extend_from_slice(COMPACT);
+
+        for chunk_size in [1, 8, input.len()] {
+            assert_eq!(detect_in_chunks(&input, chunk_size), Some(InputFormat::Xml));
+        }
+        let metadata = parse_bytes(&input).unwrap();
+        assert_eq!(titles(&metadata), vec!["Hello World"]);
+        assert_eq!(metadata.iter().filter(|m| !matches!(m, ShairportMetadata::Resynchronized { .. })).count(), 4);
+    }
+
+    #[test]
+    fn detect_binary_at_start() {
+        let input = binary(&[item(b"ssnc", b"pbeg", b"")]);
+        assert_eq!(detect_in_chunks(&input, 1), Some(InputFormat::Binary));
+        assert_eq!(detect_format(&input[..8], usize::MAX, false), Some(InputFormat::Binary));
+    }
+
+    #[test]
+    fn detect_binary_after_garbage_by_chained_headers() {
+        let items = [item(b"core", b"minm", b"one"), item(b"core", b"minm", b"two")];
+        let mut input = b"\x01\x02junk".to_vec();
+        input.extend(binary(&items));
+
+        // A single header after garbage isn't enough while streaming
+        let first_item = 6 + 12 + 3;
+        assert_eq!(detect_format(&input[..first_item], 1024, false), None);
+        assert_eq!(detect_format(&input[..first_item], 1024, true), Some(InputFormat::Binary));
+
+        assert_eq!(detect_in_chunks(&input, 1), Some(InputFormat::Binary));
+        assert_eq!(titles(&parse_bytes(&input).unwrap()), vec!["one", "two"]);
+    }
+
+    #[test]
+    fn detect_ignores_lone_header_in_junk() {
+        let mut input = b"coreminm\x00\x00\x00\x02".to_vec();
+        input.insert(0, b'x');
+        input.extend(core::iter::repeat_n(b'.', DETECT_LIMIT));
+        assert_eq!(detect_format(&input, 1024, false), Some(InputFormat::Xml));
+    }
+
+    #[test]
+    fn detect_caps_data_waiting_on_a_lone_header() {
+        // A header declaring 4 MiB keeps detection waiting only up to the
+        // buffer limit
+        let mut input = b"xcoreminm\x00\x40\x00\x00".to_vec();
+        input.extend(core::iter::repeat_n(b'.', 3 * DETECT_LIMIT));
+
+        let mut parser = AutoDetectParser::with_config(limits(1 << 24, 2 * DETECT_LIMIT));
+        for chunk in input.chunks(512) {
+            parser.feed_data(chunk);
+        }
+        assert_eq!(parser.format(), Some(InputFormat::Xml));
+
+        let mut waiting = AutoDetectParser::with_config(limits(1 << 24, 4 * DETECT_LIMIT));
+        waiting.feed_data(&input);
+        assert_eq!(waiting.format(), None);
+    }
+
+    #[test]
+    fn detect_finishes_undecided_data_at_end_of_input() {
+        let mut input = b"\x01\x02junk".to_vec();
+        input.extend(binary(&[item(b"core", b"minm", b"one")]));
+
+        let mut parser = AutoDetectParser::new();
+        parser.feed_data(&input);
+        assert_eq!(parser.format(), None);
+        parser.finish();
+        assert_eq!(parser.format(), Some(InputFormat::Binary));
+
+        let mut metadata = Vec::new();
+        while let Some(m) = parser.parse_next_metadata().unwrap() {
+            metadata.push(m);
+        }
+        assert_eq!(resyncs(&metadata), vec![(6, 6)]);
+        assert_eq!(titles(&metadata), vec!["one"]);
+    }
+}
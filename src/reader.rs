@@ -1,138 +1,186 @@
-use crate::error::{MetadataError, Result};
+use crate::codec::ShairportCodec;
+use crate::error::Result;
+use crate::fifo::{FileId, MissingPath, PathWatcher, Source, path_file_id, prepare_path};
 use crate::metadata::ShairportMetadata;
-use log::{debug, error, info, warn};
+use crate::parser::{ParseMode, ParserConfig, parse_udp_packet};
+use crate::reassembly::{ChunkReassembler, MAX_DATAGRAM_SIZE};
+use crate::ssnc::SampleClock;
+use bytes::BytesMut;
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{self, BoxStream, StreamExt};
+use log::{debug, info, warn};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::io::SeekFrom;
 use std::path::Path;
-use tokio::fs::OpenOptions;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader, stdin};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, stdin};
+use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_util::codec::{Decoder, FramedRead};
 
+// Size of the chunks handed to the parser
+const READ_CHUNK_SIZE: usize = 8192;
+
+/// Where and how to listen for metadata datagrams
+#[derive(Debug, Clone)]
+pub struct UdpConfig {
+    /// Local address and port to bind
+    pub bind_addr: SocketAddr,
+    /// Multicast group to join, e.g. shairport-sync's 226.0.0.1
+    pub multicast_group: Option<IpAddr>,
+    /// Local interface address for an IPv4 group, unspecified lets the OS pick
+    pub interface_v4: Ipv4Addr,
+    /// Interface index for an IPv6 group, 0 lets the OS pick
+    pub interface_v6: u32,
+    /// Let other readers on this host bind the same port
+    pub reuse_port: bool,
+}
+
+impl UdpConfig {
+    /// Listen on `addr`. A multicast address joins that group on its port.
+    pub fn new(addr: SocketAddr) -> Self {
+        if addr.ip().is_multicast() {
+            let unspecified = match addr {
+                SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            };
+            Self {
+                bind_addr: SocketAddr::new(unspecified, addr.port()),
+                multicast_group: Some(addr.ip()),
+                interface_v4: Ipv4Addr::UNSPECIFIED,
+                interface_v6: 0,
+                reuse_port: true,
+            }
+        } else {
+            Self {
+                bind_addr: addr,
+                multicast_group: None,
+                interface_v4: Ipv4Addr::UNSPECIFIED,
+                interface_v6: 0,
+                reuse_port: false,
+            }
+        }
+    }
+
+    /// Create the socket, joining the multicast group if one is set
+    pub fn bind(&self) -> Result<UdpSocket> {
+        let socket = Socket::new(Domain::for_address(self.bind_addr), Type::DGRAM, Some(Protocol::UDP))?;
+        if self.reuse_port {
+            socket.set_reuse_address(true)?;
+            #[cfg(unix)]
+            socket.set_reuse_port(true)?;
+        }
+        socket.bind(&self.bind_addr.into())?;
+
+        match self.multicast_group {
+            Some(IpAddr::V4(group)) => {
+                info!("Joining multicast group {} on {}", group, self.interface_v4);
+                socket.join_multicast_v4(&group, &self.interface_v4)?;
+            }
+            Some(IpAddr::V6(group)) => {
+                info!("Joining multicast group {} on interface {}", group, self.interface_v6);
+                socket.join_multicast_v6(&group, self.interface_v6)?;
+            }
+            None => {}
+        }
+
+        socket.set_nonblocking(true)?;
+        Ok(UdpSocket::from_std(socket.into())?)
+    }
+}
+
+#[derive(Clone)]
 pub struct MetadataReader {
     pub metadata_path: String,
+    pub parser_config: ParserConfig,
+    /// What to do when `metadata_path` doesn't exist yet
+    pub missing_path: MissingPath,
+    /// Only follow what is appended to a regular file, like `tail -F`
+    pub start_at_end: bool,
 }
 
 impl MetadataReader {
     pub fn new(metadata_path: String) -> Self {
+        Self::with_config(metadata_path, ParserConfig::default())
+    }
+
+    pub fn with_config(metadata_path: String, parser_config: ParserConfig) -> Self {
         Self {
             metadata_path,
+            parser_config,
+            missing_path: MissingPath::default(),
+            start_at_end: false,
         }
     }
 
+    /// Parse metadata from any async byte source: a TCP stream, a child
+    /// process's stdout, an in-memory buffer... The format is detected from
+    /// the first bytes unless set in `parser_config`. The returned
+    /// `FramedRead` is a `Stream` of `Result<ShairportMetadata>`.
+    pub fn read_from<R: AsyncRead>(&self, reader: R) -> FramedRead<R, ShairportCodec> {
+        FramedRead::with_capacity(reader, ShairportCodec::with_config(self.parser_config.clone()), READ_CHUNK_SIZE)
+    }
+
     pub async fn read_from_file(&mut self) -> Result<Vec<ShairportMetadata>> {
         let path = Path::new(&self.metadata_path);
         info!("Reading metadata from file: {}", path.display());
 
-        // Use XML parser for file reading since shairport-sync outputs XML format
-        let mut xml_parser = crate::parser::XmlMetadataParser::new();
-        let file = OpenOptions::new().read(true).open(path).await?;
-        let mut reader = BufReader::new(file);
-        let mut metadata_items = Vec::new();
-        let mut line = String::new();
-
-        loop {
-            line.clear();
-            match reader.read_line(&mut line).await {
-                Ok(0) => {
-                    info!("Reached EOF on file");
-                    break;
-                }
-                Ok(_) => {
-                    xml_parser.feed_data(line.as_bytes());
-                    
-                    while let Some(metadata) = xml_parser.parse_next_metadata()? {
-                        metadata_items.push(metadata);
-                    }
-                    
-                    xml_parser.clear_processed();
-                }
-                Err(e) => {
-                    if e.kind() == std::io::ErrorKind::WouldBlock {
-                        tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-                        continue;
-                    }
-                    return Err(MetadataError::Io(e));
-                }
-            }
-        }
+        prepare_path(path, &self.missing_path).await?;
+        let file = Source::open(path).await?;
+        let metadata_items = self.collect_metadata(file).await?;
 
         info!("Finished reading. Total metadata items: {}", metadata_items.len());
         Ok(metadata_items)
     }
 
-    pub async fn read_from_named_pipe(&mut self) -> Result<Vec<ShairportMetadata>> {
+    /// Read until EOF or until `timeout` has passed, whichever comes first.
+    /// Items parsed before the deadline are kept either way.
+    pub async fn read_from_file_with_timeout(&mut self, timeout: Duration) -> Result<ReadOutcome> {
+        let deadline = Instant::now() + timeout;
         let path = Path::new(&self.metadata_path);
-        info!("Reading metadata from named pipe: {}", path.display());
+        info!("Reading metadata from file: {} (timeout {:?})", path.display(), timeout);
 
-        // Use XML parser for named pipe reading since shairport-sync outputs XML format
-        let mut xml_parser = crate::parser::XmlMetadataParser::new();
-        let file = OpenOptions::new().read(true).open(path).await?;
-        let mut reader = BufReader::new(file);
-        let mut metadata_items = Vec::new();
-        let mut line = String::new();
+        let open = async {
+            prepare_path(path, &self.missing_path).await?;
+            Source::open(path).await
+        };
+        let file = match tokio::time::timeout_at(deadline, open).await {
+            Ok(file) => file?,
+            Err(_) => return Ok(ReadOutcome::timed_out(Vec::new())),
+        };
 
+        let mut frames = self.read_from(file);
+        let mut metadata_items = Vec::new();
         loop {
-            line.clear();
-            match reader.read_line(&mut line).await {
-                Ok(0) => {
-                    debug!("EOF on named pipe");
-                    break;
-                }
-                Ok(_) => {
-                    xml_parser.feed_data(line.as_bytes());
-
-                    while let Some(metadata) = xml_parser.parse_next_metadata()? {
-                        metadata_items.push(metadata);
-                    }
-
-                    xml_parser.clear_processed();
-                }
-                Err(e) => {
-                    if e.kind() == std::io::ErrorKind::WouldBlock {
-                        tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-                        continue;
-                    }
-                    return Err(MetadataError::Io(e));
+            match tokio::time::timeout_at(deadline, frames.next()).await {
+                Ok(Some(metadata)) => metadata_items.push(metadata?),
+                Ok(None) => {
+                    info!("Finished reading. Total metadata items: {}", metadata_items.len());
+                    return Ok(ReadOutcome {
+                        items: metadata_items,
+                        end: ReadEnd::Eof,
+                    });
                 }
+                Err(_) => return Ok(ReadOutcome::timed_out(metadata_items)),
             }
         }
-
-        Ok(metadata_items)
     }
 
-    pub async fn read_from_stdin(&mut self) -> Result<Vec<ShairportMetadata>> {
-        info!("Reading metadata from stdin (XML format detected)");
-
-        // Use XML parser for stdin since data is in XML format
-        let mut xml_parser = crate::parser::XmlMetadataParser::new();
-
-        let stdin = stdin();
-        let mut reader = BufReader::new(stdin);
-        let mut metadata_items = Vec::new();
-        let mut line = String::new();
+    pub async fn read_from_named_pipe(&mut self) -> Result<Vec<ShairportMetadata>> {
+        let path = Path::new(&self.metadata_path);
+        info!("Reading metadata from named pipe: {}", path.display());
 
-        info!("Starting stdin XML read loop...");
-        loop {
-            line.clear();
-            match reader.read_line(&mut line).await {
-                Ok(0) => {
-                    info!("Reached EOF on stdin");
-                    break; // EOF
-                }
-                Ok(_) => {
-                    debug!("Read XML line from stdin: {}", line.trim());
-                    xml_parser.feed_data(line.as_bytes());
+        prepare_path(path, &self.missing_path).await?;
+        let file = Source::open(path).await?;
+        self.collect_metadata(file).await
+    }
 
-                    while let Some(metadata) = xml_parser.parse_next_metadata()? {
-                        metadata_items.push(metadata);
-                    }
+    pub async fn read_from_stdin(&mut self) -> Result<Vec<ShairportMetadata>> {
+        info!("Reading metadata from stdin");
 
-                    xml_parser.clear_processed();
-                }
-                Err(e) => {
-                    error!("Error reading from stdin: {}", e);
-                    return Err(MetadataError::Io(e));
-                }
-            }
-        }
+        let metadata_items = self.collect_metadata(stdin()).await?;
 
         info!(
             "Finished reading from stdin. Total metadata items: {}",
@@ -141,92 +189,302 @@ impl MetadataReader {
         Ok(metadata_items)
     }
 
+    /// Stream of metadata read from stdin until EOF
+    pub fn stdin_stream(&self) -> MetadataStream {
+        info!("Starting continuous reading from stdin");
+        self.read_from(stdin()).boxed()
+    }
+
+    /// Stream that keeps following `metadata_path`, waiting for more data at
+    /// EOF. A named pipe is reopened when its writer closes it, yielding
+    /// `SourceReconnected` once a new writer shows up. A regular file is
+    /// followed like `tail -F`: when it is truncated or replaced (e.g. by
+    /// logrotate) it is read again from the start after a `SourceReconnected`. It only ends after
+    /// yielding an error; dropping it stops reading.
+    pub fn follow_file(&self) -> MetadataStream {
+        let state = FollowFile {
+            path: self.metadata_path.clone(),
+            missing_path: self.missing_path.clone(),
+            start_at_end: self.start_at_end,
+            file: None,
+            file_id: None,
+            position: 0,
+            watcher: None,
+            reconnecting: false,
+            config: self.parser_config.clone(),
+            codec: ShairportCodec::with_config(self.parser_config.clone()),
+            buffer: BytesMut::with_capacity(READ_CHUNK_SIZE),
+        };
+        until_error(state, FollowFile::next_metadata)
+    }
+
+    /// Stream of metadata datagrams received on `socket`. It only ends after
+    /// yielding an error; dropping it closes the socket.
+    pub fn udp_stream(&self, socket: UdpSocket) -> MetadataStream {
+        let state = UdpSource {
+            socket,
+            reassembler: ChunkReassembler::new(
+                self.parser_config.chunk_timeout,
+                self.parser_config.max_item_size,
+            ),
+            packet: vec![0u8; MAX_DATAGRAM_SIZE],
+            mode: self.parser_config.mode,
+            clock: SampleClock::default(),
+        };
+        until_error(state, UdpSource::next_metadata)
+    }
+
     pub async fn start_continuous_reading(
         &mut self,
         tx: mpsc::UnboundedSender<ShairportMetadata>,
     ) -> Result<()> {
-        info!("Starting continuous reading from stdin");
+        forward(self.stdin_stream(), tx).await
+    }
 
-        // Use XML parser for stdin reading since shairport-sync outputs XML format
-        let mut xml_parser = crate::parser::XmlMetadataParser::new();
-        let stdin = stdin();
-        let mut reader = BufReader::new(stdin);
-        let mut line = String::new();
+    pub async fn start_continuous_reading_from_file(
+        &mut self,
+        tx: mpsc::UnboundedSender<ShairportMetadata>,
+    ) -> Result<()> {
+        forward(self.follow_file(), tx).await
+    }
 
-        loop {
-            line.clear();
-            match reader.read_line(&mut line).await {
-                Ok(0) => break, // EOF
-                Ok(_) => {
-                    xml_parser.feed_data(line.as_bytes());
-
-                    while let Some(metadata) = xml_parser.parse_next_metadata()? {
-                        if tx.send(metadata).is_err() {
-                            info!("Receiver closed, stopping continuous reading");
-                            return Ok(());
-                        }
-                    }
+    pub async fn start_udp_reading(
+        &mut self,
+        socket: UdpSocket,
+        tx: mpsc::UnboundedSender<ShairportMetadata>,
+    ) -> Result<()> {
+        forward(self.udp_stream(socket), tx).await
+    }
 
-                    xml_parser.clear_processed();
-                }
-                Err(e) => {
-                    warn!("Error in continuous reading: {}", e);
-                    return Err(MetadataError::Io(e));
-                }
-            }
+    // Read everything up to EOF
+    async fn collect_metadata<R: AsyncRead + Unpin>(&self, reader: R) -> Result<Vec<ShairportMetadata>> {
+        let mut frames = self.read_from(reader);
+        let mut metadata_items = Vec::new();
+        while let Some(metadata) = frames.next().await {
+            metadata_items.push(metadata?);
         }
+        debug!("Reached EOF");
+        Ok(metadata_items)
+    }
+}
 
-        Ok(())
+/// Why a read with a deadline stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadEnd {
+    /// The writer closed the source or the file ended
+    Eof,
+    /// The deadline passed with the source still open
+    Timeout,
+}
+
+/// Metadata collected by a read with a deadline
+#[derive(Debug)]
+pub struct ReadOutcome {
+    pub items: Vec<ShairportMetadata>,
+    pub end: ReadEnd,
+}
+
+impl ReadOutcome {
+    fn timed_out(items: Vec<ShairportMetadata>) -> Self {
+        warn!("Timeout reading metadata after {} items", items.len());
+        Self {
+            items,
+            end: ReadEnd::Timeout,
+        }
     }
 
-    pub async fn start_continuous_reading_from_file(
-        &mut self,
-        tx: mpsc::UnboundedSender<ShairportMetadata>,
-    ) -> Result<()> {
-        let path = Path::new(&self.metadata_path);
-        info!("Starting continuous reading from: {}", path.display());
+    pub fn is_timeout(&self) -> bool {
+        self.end == ReadEnd::Timeout
+    }
+}
 
-        // Use XML parser for file reading since shairport-sync outputs XML format
-        let mut xml_parser = crate::parser::XmlMetadataParser::new();
-        
-        // Use OpenOptions for better named pipe handling
-        let file = OpenOptions::new().read(true).open(path).await?;
+/// Boxed stream of parsed metadata. Errors are yielded as items; a stream
+/// that ends without an error reached EOF.
+pub type MetadataStream = BoxStream<'static, Result<ShairportMetadata>>;
 
-        let mut reader = BufReader::new(file);
-        let mut line = String::new();
+// Build a stream from a source that is polled for one item at a time,
+// ending it after the first error
+fn until_error<S, F>(source: S, next: F) -> MetadataStream
+where
+    S: Send + 'static,
+    F: for<'a> Fn(&'a mut S) -> BoxFuture<'a, Result<ShairportMetadata>> + Copy + Send + 'static,
+{
+    stream::unfold(Some(source), move |source| {
+        async move {
+            let mut source = source?;
+            let result = next(&mut source).await;
+            let source = if result.is_ok() { Some(source) } else { None };
+            Some((result, source))
+        }
+    })
+    .boxed()
+}
 
-        loop {
-            line.clear();
-            match reader.read_line(&mut line).await {
-                Ok(0) => {
-                    debug!("No data available, waiting...");
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                    continue;
-                }
-                Ok(_) => {
-                    debug!("Read line from file: {}", line.trim());
-                    xml_parser.feed_data(line.as_bytes());
+// Send a stream to a channel until it ends or the receiver is dropped
+async fn forward(mut stream: MetadataStream, tx: mpsc::UnboundedSender<ShairportMetadata>) -> Result<()> {
+    while let Some(metadata) = stream.next().await {
+        if tx.send(metadata?).is_err() {
+            info!("Receiver closed, stopping continuous reading");
+            break;
+        }
+    }
+    Ok(())
+}
 
-                    while let Some(metadata) = xml_parser.parse_next_metadata()? {
-                        debug!("Parsed metadata: {}", metadata.get_type_name());
-                        if tx.send(metadata).is_err() {
-                            info!("Receiver closed, stopping continuous reading");
-                            return Ok(());
+// State of `MetadataReader::follow_file`
+struct FollowFile {
+    path: String,
+    missing_path: MissingPath,
+    // Skip what a regular file already holds, only on the first open
+    start_at_end: bool,
+    file: Option<Source>,
+    // Identity of the open file, to notice it being replaced
+    file_id: Option<FileId>,
+    // Bytes read from the open file, to notice it being truncated
+    position: u64,
+    watcher: Option<PathWatcher>,
+    // Set when the writer hung up, cleared once the pipe is reopened
+    reconnecting: bool,
+    config: ParserConfig,
+    codec: ShairportCodec,
+    buffer: BytesMut,
+}
+
+impl FollowFile {
+    fn next_metadata(&mut self) -> BoxFuture<'_, Result<ShairportMetadata>> {
+        async move {
+            'open: loop {
+                let file = match &mut self.file {
+                    Some(file) => file,
+                    None => {
+                        info!("Starting continuous reading from: {}", self.path);
+                        let path = Path::new(&self.path);
+                        prepare_path(path, &self.missing_path).await?;
+                        let mut source = Source::open(path).await?;
+                        self.file_id = source.file_id().await?;
+                        self.position = 0;
+                        if !source.is_fifo() {
+                            // Watch before the first read so no write is missed
+                            if self.watcher.is_none() {
+                                self.watcher = Some(PathWatcher::new(path)?);
+                            }
+                            if std::mem::take(&mut self.start_at_end) {
+                                self.position = source.seek(SeekFrom::End(0)).await?;
+                                info!("Skipping {} bytes already in {}", self.position, self.path);
+                            }
+                        }
+                        let file = self.file.insert(source);
+                        if std::mem::take(&mut self.reconnecting) {
+                            // Report the reconnection once the new writer sends
+                            file.readable().await?;
+                            info!("Reopened {}", self.path);
+                            return Ok(ShairportMetadata::SourceReconnected);
                         }
+                        file
                     }
+                };
 
-                    xml_parser.clear_processed();
+                loop {
+                    if let Some(metadata) = self.codec.decode(&mut self.buffer)? {
+                        debug!("Parsed metadata: {}", metadata.get_type_name());
+                        return Ok(metadata);
+                    }
+
+                    match file.read_buf(&mut self.buffer).await {
+                        Ok(0) if file.is_fifo() => {
+                            // Every writer closed the pipe, a restarted
+                            // shairport-sync only shows up on a new open
+                            info!("Writer closed {}, reopening", self.path);
+                            self.reset_parser();
+                            self.file = None;
+                            self.reconnecting = true;
+                            continue 'open;
+                        }
+                        Ok(0) => {
+                            let replaced = match (self.file_id, path_file_id(Path::new(&self.path)).await?) {
+                                (Some(open), Some(current)) => open != current,
+                                _ => false,
+                            };
+                            if replaced {
+                                info!("{} was replaced, reopening", self.path);
+                                self.reset_parser();
+                                self.file = None;
+                                self.reconnecting = true;
+                                continue 'open;
+                            }
+
+                            if file.file_len().await? < self.position {
+                                warn!("{} was truncated, reading from the start", self.path);
+                                self.position = file.seek(SeekFrom::Start(0)).await?;
+                                self.reset_parser();
+                                return Ok(ShairportMetadata::SourceReconnected);
+                            }
+
+                            debug!("No data available, waiting...");
+                            match &mut self.watcher {
+                                Some(watcher) => watcher.changed().await?,
+                                None => tokio::time::sleep(tokio::time::Duration::from_millis(100)).await,
+                            }
+                        }
+                        Ok(n) => {
+                            debug!("Read {} bytes from file", n);
+                            self.position += n as u64;
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                            debug!("Would block on continuous read, waiting...");
+                            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+                        }
+                        Err(e) => {
+                            warn!("Error in continuous reading: {}", e);
+                            return Err(e.into());
+                        }
+                    }
                 }
-                Err(e) => {
-                    if e.kind() == std::io::ErrorKind::WouldBlock {
-                        debug!("Would block on continuous read, waiting...");
-                        tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-                        continue;
+            }
+        }
+        .boxed()
+    }
+
+    // Start parsing afresh, dropping any incomplete item
+    fn reset_parser(&mut self) {
+        if !self.buffer.is_empty() {
+            warn!("Discarding {} bytes of an incomplete item", self.buffer.len());
+            self.buffer.clear();
+        }
+        self.codec = ShairportCodec::with_config(self.config.clone());
+    }
+}
+
+// State of `MetadataReader::udp_stream`
+struct UdpSource {
+    socket: UdpSocket,
+    reassembler: ChunkReassembler,
+    packet: Vec<u8>,
+    mode: ParseMode,
+    clock: SampleClock,
+}
+
+impl UdpSource {
+    fn next_metadata(&mut self) -> BoxFuture<'_, Result<ShairportMetadata>> {
+        async move {
+            loop {
+                let (n, peer) = self.socket.recv_from(&mut self.packet).await?;
+                debug!("Received {} byte datagram from {}", n, peer);
+
+                match parse_udp_packet(&self.packet[..n]).and_then(|item| self.reassembler.push(item)) {
+                    Ok(Some(item)) => {
+                        let metadata = self.clock.apply(ShairportMetadata::from_item(&item));
+                        debug!("Parsed metadata: {}", metadata.get_type_name());
+                        return Ok(metadata);
+                    }
+                    Ok(None) => {}
+                    Err(e) if self.mode == ParseMode::Lenient => {
+                        warn!("Ignoring datagram from {}: {}", peer, e);
                     }
-                    warn!("Error in continuous reading: {}", e);
-                    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+                    Err(e) => return Err(e),
                 }
             }
         }
+        .boxed()
     }
 }
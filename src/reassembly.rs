@@ -0,0 +1,343 @@
+use crate::error::{MetadataError, Result};
+use crate::fourcc::FourCC;
+use crate::metadata::MetadataItem;
+use crate::parser::ParserConfig;
+use log::{debug, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+// Chunk header after "ssncchnk": index(4) + total(4) + type(4) + code(4)
+const CHUNK_HEADER_LEN: usize = 16;
+
+/// Largest possible UDP payload
+pub(crate) const MAX_DATAGRAM_SIZE: usize = 65536;
+
+// Smallest `socket_msglength` shairport-sync sends with, also its default
+const MIN_DATAGRAM_SIZE: usize = 500;
+
+// Least payload a chunk datagram carries, short of the last one
+const MIN_CHUNK_DATA: usize = MIN_DATAGRAM_SIZE - 8 - CHUNK_HEADER_LEN;
+
+// Items being reassembled at once; the oldest is dropped to make room
+const MAX_PENDING_ITEMS: usize = 8;
+
+const CHUNK_CODE: FourCC = FourCC::new(b"chnk");
+
+// Chunks received so far for one type/code, by index
+struct PendingItem {
+    chunks: BTreeMap<usize, Vec<u8>>,
+    total: usize,
+    size: usize,
+    started: Instant,
+}
+
+impl PendingItem {
+    fn new(total: usize) -> Self {
+        Self {
+            chunks: BTreeMap::new(),
+            total,
+            size: 0,
+            started: Instant::now(),
+        }
+    }
+}
+
+/// Reassembles the `ssnc:chnk` datagrams shairport-sync splits large items
+/// (such as cover art) into when sending over UDP. Chunks are collected per
+/// type/code and may arrive in any order; incomplete items are dropped after
+/// the timeout. Memory only grows with the chunks actually received, and at
+/// most a few items are pending at once.
+pub struct ChunkReassembler {
+    timeout: Duration,
+    max_item_size: usize,
+    pending: HashMap<(FourCC, FourCC), PendingItem>,
+}
+
+impl ChunkReassembler {
+    pub const DEFAULT_TIMEOUT: Duration = ParserConfig::DEFAULT_CHUNK_TIMEOUT;
+
+    pub fn new(timeout: Duration, max_item_size: usize) -> Self {
+        Self {
+            timeout,
+            max_item_size,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Feed a datagram item. Ordinary items are returned as they are, chunks
+    /// are buffered until the last one arrives and the whole item is returned.
+    pub fn push(&mut self, item: MetadataItem) -> Result<Option<MetadataItem>> {
+        self.expire();
+
+        if item.item_type != FourCC::SSNC || item.code != CHUNK_CODE {
+            return Ok(Some(item));
+        }
+        if item.data.len() < CHUNK_HEADER_LEN {
+            debug!("Chunk too short for header: {} bytes", item.data.len());
+            return Err(MetadataError::InvalidFormat);
+        }
+
+        let header = &item.data[..CHUNK_HEADER_LEN];
+        let index = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let total = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let item_type = FourCC([header[8], header[9], header[10], header[11]]);
+        let code = FourCC([header[12], header[13], header[14], header[15]]);
+        let data = &item.data[CHUNK_HEADER_LEN..];
+
+        debug!("Chunk {}/{} of {}:{} with {} bytes", index + 1, total, item_type, code, data.len());
+
+        if index >= total {
+            debug!("Chunk index {} out of range for {} chunks", index, total);
+            return Err(MetadataError::InvalidFormat);
+        }
+        // No more chunks than a largest item sent in the smallest datagrams
+        // needs, the received bytes are checked against the limit as well
+        if total > self.max_item_size.div_ceil(MIN_CHUNK_DATA) {
+            return Err(MetadataError::ItemTooLarge {
+                item_type,
+                code,
+                offset: 0,
+                length: total.saturating_mul(MIN_CHUNK_DATA),
+                limit: self.max_item_size,
+            });
+        }
+
+        let key = (item_type, code);
+        if !self.pending.contains_key(&key) {
+            self.make_room();
+        }
+        let pending = self.pending.entry(key).or_insert_with(|| PendingItem::new(total));
+
+        match pending.chunks.get(&index) {
+            // The same datagram delivered twice
+            Some(chunk) if pending.total == total && chunk == data => {
+                debug!("Ignoring duplicate chunk {} of {}:{}", index + 1, key.0, key.1);
+                return Ok(None);
+            }
+            // A different total or a different chunk at a known index means
+            // a new item has started before the previous one was complete
+            Some(_) => restart(pending, &key, total),
+            None if pending.total != total => restart(pending, &key, total),
+            None => {}
+        }
+
+        pending.size += data.len();
+        if pending.size > self.max_item_size {
+            let size = pending.size;
+            self.pending.remove(&key);
+            return Err(MetadataError::ItemTooLarge {
+                item_type: key.0,
+                code: key.1,
+                offset: 0,
+                length: size,
+                limit: self.max_item_size,
+            });
+        }
+        pending.chunks.insert(index, data.to_vec());
+
+        if pending.chunks.len() < total {
+            return Ok(None);
+        }
+
+        let pending = self.pending.remove(&key).expect("pending item exists");
+        let data = pending.chunks.into_values().flatten().collect::<Vec<u8>>();
+        debug!("Reassembled {}:{} from {} chunks, {} bytes", key.0, key.1, total, data.len());
+
+        Ok(Some(MetadataItem {
+            item_type: key.0,
+            code: key.1,
+            data,
+        }))
+    }
+
+    /// Drop items that have been waiting for missing chunks too long
+    pub fn expire(&mut self) {
+        let timeout = self.timeout;
+        self.pending.retain(|(item_type, code), pending| {
+            let keep = pending.started.elapsed() < timeout;
+            if !keep {
+                warn!(
+                    "Dropping {}:{} after {:?}, got {} of {} chunks",
+                    item_type,
+                    code,
+                    timeout,
+                    pending.chunks.len(),
+                    pending.total
+                );
+            }
+            keep
+        });
+    }
+
+    // Drop the oldest pending item if there is no room for another
+    fn make_room(&mut self) {
+        if self.pending.len() < MAX_PENDING_ITEMS {
+            return;
+        }
+        let oldest = self.pending.iter().min_by_key(|(_, pending)| pending.started).map(|(key, _)| *key);
+        if let Some((item_type, code)) = oldest
+            && let Some(pending) = self.pending.remove(&(item_type, code))
+        {
+            warn!(
+                "Dropping {}:{} to make room, got {} of {} chunks",
+                item_type,
+                code,
+                pending.chunks.len(),
+                pending.total
+            );
+        }
+    }
+}
+
+fn restart(pending: &mut PendingItem, key: &(FourCC, FourCC), total: usize) {
+    warn!(
+        "Dropping incomplete {}:{} ({} of {} chunks), a new one started",
+        key.0,
+        key.1,
+        pending.chunks.len(),
+        pending.total
+    );
+    *pending = PendingItem::new(total);
+}
+
+impl Default for ChunkReassembler {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_TIMEOUT, crate::parser::ParserConfig::DEFAULT_MAX_ITEM_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(code: &[u8; 4], index: u32, total: u32, data: &[u8]) -> MetadataItem {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&index.to_be_bytes());
+        payload.extend_from_slice(&total.to_be_bytes());
+        payload.extend_from_slice(b"ssnc");
+        payload.extend_from_slice(code);
+        payload.extend_from_slice(data);
+        MetadataItem {
+            item_type: FourCC::SSNC,
+            code: CHUNK_CODE,
+            data: payload,
+        }
+    }
+
+    fn picture(data: &[u8]) -> Option<MetadataItem> {
+        Some(MetadataItem {
+            item_type: FourCC::SSNC,
+            code: FourCC::new(b"PICT"),
+            data: data.to_vec(),
+        })
+    }
+
+    #[test]
+    fn passes_ordinary_items_through() {
+        let mut reassembler = ChunkReassembler::default();
+        assert_eq!(reassembler.push(picture(b"abc").unwrap()).unwrap(), picture(b"abc"));
+    }
+
+    #[test]
+    fn reassembles_chunks_in_any_order() {
+        let mut reassembler = ChunkReassembler::default();
+        assert_eq!(reassembler.push(chunk(b"PICT", 2, 3, b"ef")).unwrap(), None);
+        assert_eq!(reassembler.push(chunk(b"PICT", 0, 3, b"ab")).unwrap(), None);
+        assert_eq!(reassembler.push(chunk(b"PICT", 1, 3, b"cd")).unwrap(), picture(b"abcdef"));
+        assert!(reassembler.pending.is_empty());
+    }
+
+    #[test]
+    fn drops_items_after_timeout() {
+        let mut reassembler = ChunkReassembler::new(Duration::from_millis(10), 1 << 20);
+        assert_eq!(reassembler.push(chunk(b"PICT", 0, 2, b"ab")).unwrap(), None);
+        std::thread::sleep(Duration::from_millis(20));
+        reassembler.expire();
+        assert!(reassembler.pending.is_empty());
+
+        // The late chunk starts a new item instead of completing the old one
+        assert_eq!(reassembler.push(chunk(b"PICT", 1, 2, b"cd")).unwrap(), None);
+    }
+
+    #[test]
+    fn ignores_repeated_chunks_and_restarts_on_changed_ones() {
+        let mut reassembler = ChunkReassembler::default();
+        assert_eq!(reassembler.push(chunk(b"PICT", 0, 2, b"ab")).unwrap(), None);
+        assert_eq!(reassembler.push(chunk(b"PICT", 0, 2, b"ab")).unwrap(), None);
+        assert_eq!(reassembler.push(chunk(b"PICT", 1, 2, b"cd")).unwrap(), picture(b"abcd"));
+
+        // Different data at a known index is the start of the next item
+        assert_eq!(reassembler.push(chunk(b"PICT", 0, 2, b"ab")).unwrap(), None);
+        assert_eq!(reassembler.push(chunk(b"PICT", 0, 2, b"xy")).unwrap(), None);
+        assert_eq!(reassembler.push(chunk(b"PICT", 1, 2, b"cd")).unwrap(), picture(b"xycd"));
+    }
+
+    #[test]
+    fn rejects_oversize_totals() {
+        let mut reassembler = ChunkReassembler::default();
+        assert!(matches!(
+            reassembler.push(chunk(b"PICT", 0, 16_000_000, b"ab")),
+            Err(MetadataError::ItemTooLarge { .. })
+        ));
+        assert!(reassembler.pending.is_empty());
+
+        // The largest total allowed only holds the chunks that arrived
+        let most = ParserConfig::DEFAULT_MAX_ITEM_SIZE.div_ceil(MIN_CHUNK_DATA) as u32;
+        assert_eq!(reassembler.push(chunk(b"PICT", 0, most, b"ab")).unwrap(), None);
+        assert!(matches!(
+            reassembler.push(chunk(b"PICT", 0, most + 1, b"ab")),
+            Err(MetadataError::ItemTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn reassembles_cover_art_from_default_size_datagrams() {
+        // 200 KB of cover art as shairport-sync sends it with the default
+        // 500-byte socket_msglength
+        let art: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let parts: Vec<&[u8]> = art.chunks(MIN_CHUNK_DATA).collect();
+        let total = parts.len() as u32;
+        assert_eq!(total, 421);
+
+        let mut reassembler = ChunkReassembler::default();
+        let mut result = None;
+        for (index, part) in parts.iter().enumerate() {
+            let mut datagram = b"ssncchnk".to_vec();
+            datagram.extend_from_slice(&chunk(b"PICT", index as u32, total, part).data);
+            assert!(datagram.len() <= MIN_DATAGRAM_SIZE);
+            let item = crate::parser::parse_udp_packet(&datagram).unwrap();
+            result = reassembler.push(item).unwrap();
+        }
+        assert_eq!(result, picture(&art));
+    }
+
+    #[test]
+    fn rejects_items_growing_past_the_limit() {
+        let limit = 1001;
+        let full = vec![0u8; 1000];
+        let mut reassembler = ChunkReassembler::new(ChunkReassembler::DEFAULT_TIMEOUT, limit);
+        assert_eq!(reassembler.push(chunk(b"PICT", 0, 2, &full)).unwrap(), None);
+        assert!(matches!(
+            reassembler.push(chunk(b"PICT", 1, 2, &full)),
+            Err(MetadataError::ItemTooLarge { length, .. }) if length == 2000
+        ));
+        assert!(reassembler.pending.is_empty());
+    }
+
+    #[test]
+    fn limits_pending_items() {
+        let mut reassembler = ChunkReassembler::default();
+        let codes: Vec<[u8; 4]> = (0..=MAX_PENDING_ITEMS as u8).map(|i| [b'c', b'o', b'd', b'0' + i]).collect();
+        for code in &codes {
+            assert_eq!(reassembler.push(chunk(code, 0, 2, b"ab")).unwrap(), None);
+        }
+        assert_eq!(reassembler.pending.len(), MAX_PENDING_ITEMS);
+
+        // The oldest was dropped, the newest completes
+        assert_eq!(reassembler.push(chunk(&codes[0], 1, 2, b"cd")).unwrap(), None);
+        let last = codes.last().unwrap();
+        let item = reassembler.push(chunk(last, 1, 2, b"cd")).unwrap().unwrap();
+        assert_eq!(item.code, FourCC::new(last));
+        assert_eq!(item.data, b"abcd");
+    }
+}
@@ -0,0 +1,304 @@
+//! Values of the `ssnc` items shairport-sync sends as text
+
+use crate::error::MetadataError;
+use crate::metadata::ShairportMetadata;
+use alloc::format;
+use core::fmt;
+use core::str::FromStr;
+use core::time::Duration;
+
+/// Volume from `ssnc:pvol`, sent as
+/// `"airplay_volume,volume_db,lowest_db,highest_db"`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Volume {
+    /// Slider position from -30.0 (quietest) to 0.0 (loudest), or -144.0
+    /// when muted
+    pub airplay_volume: f64,
+    /// Attenuation applied to the output
+    pub volume_db: f64,
+    /// Attenuation at the bottom of the output's range
+    pub lowest_db: f64,
+    /// Attenuation at the top of the output's range
+    pub highest_db: f64,
+}
+
+impl Volume {
+    /// `airplay_volume` of a muted source
+    pub const MUTED: f64 = -144.0;
+    /// Lowest `airplay_volume` short of muting
+    pub const AIRPLAY_MIN: f64 = -30.0;
+
+    pub fn is_muted(&self) -> bool {
+        self.airplay_volume <= Self::MUTED
+    }
+
+    /// Slider position as a percentage, 0.0 when muted. The AirPlay slider
+    /// maps linearly onto -30.0..=0.0.
+    pub fn percent(&self) -> f64 {
+        if self.is_muted() {
+            return 0.0;
+        }
+        ((self.airplay_volume - Self::AIRPLAY_MIN) / -Self::AIRPLAY_MIN * 100.0).clamp(0.0, 100.0)
+    }
+}
+
+impl FromStr for Volume {
+    type Err = MetadataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || MetadataError::Parse(format!("invalid volume '{}'", s));
+        let mut fields = s.split(',').map(|field| field.trim().parse::<f64>());
+        let mut next = || fields.next().ok_or_else(invalid)?.map_err(|_| invalid());
+        let volume = Self {
+            airplay_volume: next()?,
+            volume_db: next()?,
+            lowest_db: next()?,
+            highest_db: next()?,
+        };
+        if fields.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(volume)
+    }
+}
+
+// The format shairport-sync writes
+impl fmt::Display for Volume {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2},{:.2},{:.2},{:.2}",
+            self.airplay_volume, self.volume_db, self.lowest_db, self.highest_db
+        )
+    }
+}
+
+/// Playback position from `ssnc:prgr`, sent as `"start/current/end"` RTP
+/// timestamps. They count frames and wrap around at `u32::MAX`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// RTP time of the first frame of the track
+    pub start: u32,
+    /// RTP time of the frame being played
+    pub current: u32,
+    /// RTP time of the frame after the last one
+    pub end: u32,
+    /// Frames per second, from the last `core:assr` when the parser saw one
+    pub sample_rate: u32,
+}
+
+impl Progress {
+    pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+    pub fn with_sample_rate(self, sample_rate: u32) -> Self {
+        Self { sample_rate, ..self }
+    }
+
+    /// Frames played, 0 while `current` is still before `start`
+    pub fn elapsed_frames(&self) -> u32 {
+        // A wrapped difference above i32::MAX means current is behind start
+        let frames = self.current.wrapping_sub(self.start);
+        if frames > i32::MAX as u32 { 0 } else { frames }
+    }
+
+    /// Frames in the whole track
+    pub fn duration_frames(&self) -> u32 {
+        self.end.wrapping_sub(self.start)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.frames_to_duration(self.elapsed_frames())
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.frames_to_duration(self.duration_frames())
+    }
+
+    /// Share of the track played, from 0.0 to 1.0
+    pub fn fraction(&self) -> f64 {
+        match self.duration_frames() {
+            0 => 0.0,
+            total => (f64::from(self.elapsed_frames()) / f64::from(total)).min(1.0),
+        }
+    }
+
+    fn frames_to_duration(&self, frames: u32) -> Duration {
+        let nanos = u64::from(frames) * 1_000_000_000;
+        Duration::from_nanos(nanos.checked_div(self.sample_rate.into()).unwrap_or(0))
+    }
+}
+
+impl FromStr for Progress {
+    type Err = MetadataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || MetadataError::Parse(format!("invalid progress '{}'", s));
+        let mut fields = s.split('/').map(|field| field.trim().parse::<u32>());
+        let mut next = || fields.next().ok_or_else(invalid)?.map_err(|_| invalid());
+        let progress = Self {
+            start: next()?,
+            current: next()?,
+            end: next()?,
+            sample_rate: Self::DEFAULT_SAMPLE_RATE,
+        };
+        if fields.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(progress)
+    }
+}
+
+// The format shairport-sync writes
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.start, self.current, self.end)
+    }
+}
+
+/// A frame and when it is played, from `ssnc:phbt` and `ssnc:phb0`, sent as
+/// `"frame/time"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTime {
+    /// RTP time of the frame
+    pub frame: u32,
+    /// Local monotonic time the frame is played at, in nanoseconds
+    pub time_ns: u64,
+}
+
+impl FromStr for FrameTime {
+    type Err = MetadataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || MetadataError::Parse(format!("invalid frame time '{}'", s));
+        let (frame, time_ns) = s.split_once('/').ok_or_else(invalid)?;
+        Ok(Self {
+            frame: frame.trim().parse().map_err(|_| invalid())?,
+            time_ns: time_ns.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+// The format shairport-sync writes
+impl fmt::Display for FrameTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.frame, self.time_ns)
+    }
+}
+
+// Gives progress events the sample rate of the track being played
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SampleClock {
+    sample_rate: u32,
+}
+
+impl SampleClock {
+    pub(crate) fn apply(&mut self, metadata: ShairportMetadata) -> ShairportMetadata {
+        match metadata {
+            ShairportMetadata::SampleRate(rate) if rate > 0 => {
+                self.sample_rate = rate;
+                metadata
+            }
+            ShairportMetadata::Progress(progress) => {
+                ShairportMetadata::Progress(progress.with_sample_rate(self.sample_rate))
+            }
+            metadata => metadata,
+        }
+    }
+}
+
+impl Default for SampleClock {
+    fn default() -> Self {
+        Self {
+            sample_rate: Progress::DEFAULT_SAMPLE_RATE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::encode_binary_item;
+    use crate::fourcc::FourCC;
+    use crate::metadata::MetadataItem;
+    use crate::parser::parse_binary_bytes;
+    use alloc::vec::Vec;
+
+    const RATE: u32 = Progress::DEFAULT_SAMPLE_RATE;
+
+    fn progress(start: u32, current: u32, end: u32) -> Progress {
+        Progress { start, current, end, sample_rate: RATE }
+    }
+
+    #[test]
+    fn elapsed_counts_across_the_wrap() {
+        // Started a second before the counter wrapped, now two seconds in
+        let start = u32::MAX - RATE + 1;
+        let p = progress(start, RATE, start.wrapping_add(10 * RATE));
+        assert!(start > p.current);
+        assert_eq!(p.elapsed_frames(), 2 * RATE);
+        assert_eq!(p.elapsed(), Duration::from_secs(2));
+        assert_eq!(p.fraction(), 0.2);
+    }
+
+    #[test]
+    fn duration_counts_when_end_wraps_past_start() {
+        let start = u32::MAX - 5 * RATE + 1;
+        let end = start.wrapping_add(180 * RATE);
+        assert!(end < start);
+        let p = progress(start, start.wrapping_add(RATE), end);
+        assert_eq!(p.duration_frames(), 180 * RATE);
+        assert_eq!(p.duration(), Duration::from_secs(180));
+        assert_eq!(p.elapsed(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn elapsed_is_zero_before_start() {
+        let p = progress(1000, 900, 1000 + RATE);
+        assert_eq!(p.elapsed(), Duration::ZERO);
+        assert_eq!(p.fraction(), 0.0);
+    }
+
+    #[test]
+    fn progress_uses_the_rate_from_assr() {
+        let items = [
+            (FourCC::SSNC, b"prgr", b"0/44100/88200".to_vec()),
+            (FourCC::CORE, b"assr", 48000u32.to_be_bytes().to_vec()),
+            (FourCC::SSNC, b"prgr", b"0/96000/480000".to_vec()),
+        ];
+        let input: Vec<u8> = items
+            .iter()
+            .flat_map(|(item_type, code, data)| {
+                let item = MetadataItem {
+                    item_type: *item_type,
+                    code: FourCC::new(code),
+                    data: data.clone(),
+                };
+                encode_binary_item(&item).unwrap()
+            })
+            .collect();
+
+        let progress: Vec<Progress> = parse_binary_bytes(&input)
+            .unwrap()
+            .into_iter()
+            .filter_map(|m| match m {
+                ShairportMetadata::Progress(p) => Some(p),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(progress[0].sample_rate, RATE);
+        assert_eq!(progress[0].elapsed(), Duration::from_secs(1));
+        assert_eq!(progress[1].sample_rate, 48000);
+        assert_eq!(progress[1].elapsed(), Duration::from_secs(2));
+        assert_eq!(progress[1].duration(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn sample_clock_ignores_a_zero_rate() {
+        let mut clock = SampleClock::default();
+        clock.apply(ShairportMetadata::SampleRate(0));
+        let ShairportMetadata::Progress(p) = clock.apply(ShairportMetadata::Progress(progress(0, 0, 0))) else {
+            panic!("not a progress event");
+        };
+        assert_eq!(p.sample_rate, RATE);
+    }
+}
@@ -0,0 +1,57 @@
+use proptest::prelude::*;
+use shairport_sync_metadata_reader_rs::{FourCC, MetadataItem, MetadataParser, encode_binary_item};
+
+// Any type and code, an in-sync parser takes every header as it comes
+fn item() -> impl Strategy<Value = MetadataItem> {
+    (
+        any::<[u8; 4]>().prop_map(FourCC),
+        any::<[u8; 4]>().prop_map(FourCC),
+        prop::collection::vec(any::<u8>(), 0..2048),
+    )
+        .prop_map(|(item_type, code, data)| MetadataItem {
+            item_type,
+            code,
+            data,
+        })
+}
+
+fn parse_all(parser: &mut MetadataParser, parsed: &mut Vec<MetadataItem>) {
+    while let Some(item) = parser.parse_next_item().unwrap() {
+        parsed.push(item);
+    }
+    parser.clear_processed();
+}
+
+proptest! {
+    #[test]
+    fn encode_then_parse_is_identity(items in prop::collection::vec(item(), 1..16)) {
+        let mut parser = MetadataParser::new();
+        let mut parsed = Vec::new();
+        for item in &items {
+            parser.feed_data(&encode_binary_item(item).unwrap());
+        }
+        parse_all(&mut parser, &mut parsed);
+
+        prop_assert_eq!(parsed, items);
+    }
+
+    #[test]
+    fn identity_holds_for_any_read_size(
+        items in prop::collection::vec(item(), 1..16),
+        chunk_size in 1usize..64,
+    ) {
+        let encoded: Vec<u8> = items
+            .iter()
+            .flat_map(|item| encode_binary_item(item).unwrap())
+            .collect();
+
+        let mut parser = MetadataParser::new();
+        let mut parsed = Vec::new();
+        for chunk in encoded.chunks(chunk_size) {
+            parser.feed_data(chunk);
+            parse_all(&mut parser, &mut parsed);
+        }
+
+        prop_assert_eq!(parsed, items);
+    }
+}
@@ -0,0 +1,71 @@
+#![cfg(feature = "tokio")]
+
+use futures::StreamExt;
+use shairport_sync_metadata_reader_rs::{
+    FourCC, MetadataItem, MetadataIter, MetadataReader, ShairportMetadata, encode_binary_item,
+    parse_bytes,
+};
+use std::io::Cursor;
+
+fn binary(items: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    items
+        .iter()
+        .flat_map(|(code, data)| {
+            let item = MetadataItem {
+                item_type: FourCC::CORE,
+                code: FourCC::new(code),
+                data: data.to_vec(),
+            };
+            encode_binary_item(&item).unwrap()
+        })
+        .collect()
+}
+
+// Comparable form of the metadata, events included
+fn summary(metadata: &[ShairportMetadata]) -> Vec<(&'static str, String, Option<MetadataItem>)> {
+    metadata
+        .iter()
+        .map(|m| (m.get_type_name(), m.get_data_as_string(), m.to_item()))
+        .collect()
+}
+
+fn from_iter(input: &[u8]) -> Vec<ShairportMetadata> {
+    MetadataIter::new(Cursor::new(input.to_vec()))
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+fn from_codec(input: &[u8]) -> Vec<ShairportMetadata> {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    runtime.block_on(async {
+        let reader = MetadataReader::new(String::new());
+        reader
+            .read_from(input)
+            .map(|metadata| metadata.unwrap())
+            .collect()
+            .await
+    })
+}
+
+#[test]
+fn streaming_matches_one_shot_parsing() {
+    let mut junk_then_one = b"\x00\xffjunk".to_vec();
+    junk_then_one.extend(binary(&[(b"minm", b"one")]));
+    let mut junk_then_two = b"\x00\xffjunk".to_vec();
+    junk_then_two.extend(binary(&[(b"minm", b"one"), (b"asar", b"two")]));
+
+    let inputs = [
+        junk_then_one,
+        junk_then_two,
+        binary(&[(b"minm", b"one")]),
+        include_bytes!("../test_input.xml").to_vec(),
+        b"<item><type>636f7265</type><code>6d696e6d</code><length>0</length></item>".to_vec(),
+    ];
+
+    for input in &inputs {
+        let expected = parse_bytes(input).unwrap();
+        assert!(!expected.is_empty());
+        assert_eq!(summary(&from_iter(input)), summary(&expected));
+        assert_eq!(summary(&from_codec(input)), summary(&expected));
+    }
+}
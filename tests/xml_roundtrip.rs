@@ -0,0 +1,78 @@
+use shairport_sync_metadata_reader_rs::parser::XmlMetadataParser;
+use shairport_sync_metadata_reader_rs::{FourCC, MetadataItem, ShairportMetadata, encode_xml_item, parse_xml_bytes};
+
+fn item(item_type: &[u8; 4], code: &[u8; 4], data: &[u8]) -> MetadataItem {
+    MetadataItem {
+        item_type: FourCC::new(item_type),
+        code: FourCC::new(code),
+        data: data.to_vec(),
+    }
+}
+
+fn parse_items(xml: &[u8]) -> Vec<MetadataItem> {
+    let mut parser = XmlMetadataParser::new();
+    parser.feed_data(xml);
+    let mut items = Vec::new();
+    while let Some(item) = parser.parse_next_item().unwrap() {
+        items.push(item);
+    }
+    items
+}
+
+// parse -> to_item -> encode -> parse gives back the same item and bytes
+fn assert_round_trip(original: &MetadataItem) {
+    let xml = encode_xml_item(original).unwrap();
+    let metadata = parse_xml_bytes(&xml).unwrap();
+    assert_eq!(metadata.len(), 1, "{:?}", metadata);
+
+    let item = metadata[0].to_item().unwrap();
+    assert_eq!(&item, original, "decoded as {:?}", metadata[0]);
+    let encoded = encode_xml_item(&item).unwrap();
+    assert_eq!(encoded, xml);
+    assert_eq!(parse_items(&encoded), vec![item]);
+}
+
+#[test]
+fn fixture_round_trips_byte_for_byte() {
+    let xml = include_bytes!("../test_input.xml");
+    let items = parse_items(xml);
+    assert_eq!(items.len(), 4);
+
+    let mut encoded = Vec::new();
+    for item in &items {
+        assert_round_trip(item);
+        encoded.extend(encode_xml_item(item).unwrap());
+    }
+    assert_eq!(encoded, xml);
+}
+
+#[test]
+fn non_utf8_text_is_kept_as_bytes() {
+    let original = item(b"core", b"minm", &[0xe9, 0x41]);
+    assert_round_trip(&original);
+    assert!(matches!(
+        &parse_xml_bytes(&encode_xml_item(&original).unwrap()).unwrap()[0],
+        ShairportMetadata::Other { data, .. } if data == &[0xe9, 0x41]
+    ));
+}
+
+#[test]
+fn pictures_keep_their_code() {
+    let jpeg = [0xff, 0xd8, 0xff, 0xe0, 0x00, 0x10];
+    for (item_type, code) in [(b"ssnc", b"PICT"), (b"ssnc", b"pict"), (b"core", b"PICT"), (b"pict", b"abcd")] {
+        let original = item(item_type, code, &jpeg);
+        assert_round_trip(&original);
+        assert!(matches!(
+            &parse_xml_bytes(&encode_xml_item(&original).unwrap()).unwrap()[0],
+            ShairportMetadata::Picture { .. }
+        ));
+    }
+}
+
+#[test]
+fn other_items_round_trip() {
+    assert_round_trip(&item(b"ssnc", b"zzzz", &[0, 1, 2, 0xff]));
+    assert_round_trip(&item(b"dmap", b"\x00\x01\x02\x03", b"text"));
+    assert_round_trip(&item(b"core", b"asar", "Sigur Rós".as_bytes()));
+    assert_round_trip(&item(b"ssnc", b"pbeg", b""));
+}